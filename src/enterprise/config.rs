@@ -16,8 +16,18 @@ use crate::{
 };
 
 use super::{
-    license::LicenseKey, llm::AiApiConfig, AlertContent, AlertContentToken, AlertMethod,
-    Enterprise, MetricAlert, MetricStore, SpamFilterLlmConfig, TraceStore, Undelete, // 引入企业版相关模块
+    license::LicenseKey,
+    llm::{
+        audit::AiAuditLog,
+        gate::LLM_GATE_VARIABLES,
+        pool::{AiApiPool, AiEndpoint},
+        tenant::{AiTenantGuard, AiTenantPolicy},
+        AiApiConfig, ApiType,
+    },
+    AiAssistantConfig, AiEmbeddingConfig, AiSieveConfig, AlertContent, AlertContentToken,
+    AlertMethod, Enterprise, LlmClassificationMode, LlmPromptTemplate, LlmPromptToken,
+    LlmPromptVariable, LlmResponseFormat, LlmScoreRule, MetricAlert, MetricStore,
+    PhishingLlmConfig, SpamFilterLlmConfig, TraceStore, Undelete, // 引入企业版相关模块
 };
 
 // 为Enterprise结构体实现解析方法
@@ -171,18 +181,56 @@ impl Enterprise {
             None
         };
 
-        // 解析AI API
+        // 解析AI审计日志，写入追踪存储
+        let ai_audit =
+            AiAuditLog::parse(config, trace_store.as_ref().map(|t| &t.store)).map(Arc::new);
+
+        // 解析AI API，租户策略在解析后填充
+        let ai_tenant_guard = Arc::new(AiTenantGuard::default());
         let mut ai_apis = AHashMap::new();
         for id in config
             .sub_keys("enterprise.ai", ".url")
             .map(|s| s.to_string())
             .collect::<Vec<_>>()
         {
-            if let Some(api) = AiApiConfig::parse(config, &id) {
+            if let Some(mut api) = AiApiConfig::parse(config, &id) {
+                // 令牌用量计数器保存在数据存储中，在重启和集群节点之间共享
+                api.usage = Arc::new(api.usage.as_ref().clone().with_store(data.clone()));
+                api.audit = ai_audit.clone();
+                api.tenants = ai_tenant_guard.clone();
                 ai_apis.insert(id, api.into());
             }
         }
 
+        // 解析AI API池
+        let mut ai_pools = AHashMap::new();
+        for id in config
+            .sub_keys("enterprise.ai-pool", "")
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+        {
+            if let Some(pool) = AiApiPool::parse(config, &id, &ai_apis) {
+                ai_pools.insert(id, pool.into());
+            }
+        }
+
+        // 解析租户的AI策略
+        let mut ai_tenants = AHashMap::new();
+        for tenant in config
+            .sub_keys("enterprise.ai-tenant", ".policy")
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+        {
+            if let Some(policy) = AiTenantPolicy::parse(config, &tenant, &ai_apis, &ai_pools) {
+                ai_tenants.insert(tenant, policy);
+            }
+        }
+        if !ai_tenants.is_empty() {
+            if let Err(err) = ai_tenant_guard.init(data, &ai_tenants).await {
+                config.new_build_error("enterprise.ai-tenant", err.to_string());
+            }
+        }
+
         Some(Enterprise {
             license,
             undelete: config
@@ -193,38 +241,98 @@ impl Enterprise {
             trace_store,
             metrics_store,
             metrics_alerts: parse_metric_alerts(config),
-            spam_filter_llm: SpamFilterLlmConfig::parse(config, &ai_apis),
+            spam_filter_llm: SpamFilterLlmConfig::parse(config, &ai_apis, &ai_pools),
+            ai_assistant: AiAssistantConfig::parse(config, &ai_apis, &ai_pools),
+            ai_sieve: AiSieveConfig::parse(config, &ai_apis, &ai_pools),
+            phishing_llm: PhishingLlmConfig::parse(config, &ai_apis, &ai_pools),
+            ai_embedding: AiEmbeddingConfig::parse(config, &ai_apis),
             ai_apis,
+            ai_pools,
+            ai_tenants,
+            ai_audit,
         })
     }
 }
 
+// 为AiTenantPolicy实现解析方法。配置无效时禁用该租户的AI功能，而不是回退到默认端点
+impl AiTenantPolicy {
+    pub fn parse(
+        config: &mut Config,
+        tenant: &str,
+        models: &AHashMap<String, Arc<AiApiConfig>>,
+        pools: &AHashMap<String, Arc<AiApiPool>>,
+    ) -> Option<Self> {
+        let key = ("enterprise.ai-tenant", tenant, "policy");
+        let policy = config.value(key).unwrap_or("default").to_string();
+        match policy.as_str() {
+            "default" => None,
+            "disable" => Some(AiTenantPolicy::Disable),
+            "override" => Some(
+                parse_ai_endpoint(
+                    config,
+                    &format!("enterprise.ai-tenant.{tenant}.model"),
+                    models,
+                    pools,
+                )
+                .map(AiTenantPolicy::Override)
+                .unwrap_or(AiTenantPolicy::Disable),
+            ),
+            _ => {
+                config.new_build_error(
+                    key,
+                    "Invalid AI tenant policy, expected \"default\", \"override\" or \"disable\"",
+                );
+                Some(AiTenantPolicy::Disable)
+            }
+        }
+    }
+}
+
 // 为SpamFilterLlmConfig结构体实现解析方法
 impl SpamFilterLlmConfig {
-    pub fn parse(config: &mut Config, models: &AHashMap<String, Arc<AiApiConfig>>) -> Option<Self> {
+    pub fn parse(
+        config: &mut Config,
+        models: &AHashMap<String, Arc<AiApiConfig>>,
+        pools: &AHashMap<String, Arc<AiApiPool>>,
+    ) -> Option<Self> {
         if !config
             .property_or_default::<bool>("spam-filter.llm.enable", "false")
             .unwrap_or_default()
         {
             return None;
         }
-        let model = config.value_require_non_empty("spam-filter.llm.model")?;
-        let model = if let Some(model) = models.get(model) {
-            model.clone()
-        } else {
-            let message = format!("Model {model:?} not found in AI API configuration");
-            config.new_build_error("spam-filter.llm.model", message);
-            return None;
-        };
+        let model = parse_ai_endpoint(config, "spam-filter.llm.model", models, pools)?;
 
-        let llm = SpamFilterLlmConfig {
+        // 解析提示模板
+        let prompt = config
+            .value_require_non_empty("spam-filter.llm.prompt")?
+            .to_string();
+        let prompt = parse_llm_template(
+            config,
+            "spam-filter.llm.prompt",
+            "spam-filter.llm.limits",
+            &prompt,
+            true,
+        );
+        let message = config
+            .value("spam-filter.llm.message")
+            .unwrap_or(DEFAULT_LLM_MESSAGE)
+            .to_string();
+        let message = parse_llm_template(
+            config,
+            "spam-filter.llm.message",
+            "spam-filter.llm.limits",
+            &message,
+            false,
+        );
+
+        let mut llm = SpamFilterLlmConfig {
             model,
             temperature: config
                 .property_or_default("spam-filter.llm.temperature", "0.5")
                 .unwrap_or(0.5),
-            prompt: config
-                .value_require_non_empty("spam-filter.llm.prompt")?
-                .to_string(),
+            prompt,
+            message,
             separator: config
                 .value_require_non_empty("spam-filter.llm.separator")
                 .unwrap_or_default()
@@ -244,6 +352,65 @@ impl SpamFilterLlmConfig {
                 .values("spam-filter.llm.confidence")
                 .map(|(_, v)| v.trim().to_uppercase())
                 .collect(),
+            examples: parse_llm_examples(config),
+            response_format: match config
+                .value("spam-filter.llm.response-format")
+                .unwrap_or("text")
+            {
+                "json" => LlmResponseFormat::Json,
+                "text" => LlmResponseFormat::Text,
+                _ => {
+                    config.new_build_error(
+                        "spam-filter.llm.response-format",
+                        "Invalid response format, expected \"text\" or \"json\"",
+                    );
+                    return None;
+                }
+            },
+            scores: Vec::new(),
+            condition: if config.value("spam-filter.llm.condition").is_some() {
+                Some(Expression::try_parse(
+                    config,
+                    "spam-filter.llm.condition",
+                    &TokenMap::default().with_variables_map(
+                        LLM_GATE_VARIABLES
+                            .iter()
+                            .map(|(name, id)| (name.to_string(), *id)),
+                    ),
+                )?)
+            } else {
+                None
+            },
+            sample_rate: config
+                .property_or_default::<f64>("spam-filter.llm.sample-rate", "100")
+                .unwrap_or(100.0)
+                .clamp(0.0, 100.0),
+            exclude_domains: config
+                .values("spam-filter.llm.exclude-domains")
+                .map(|(_, v)| v.trim().trim_start_matches('.').to_lowercase())
+                .filter(|v| !v.is_empty())
+                .collect(),
+            mode: match config.value("spam-filter.llm.mode").unwrap_or("inline") {
+                "inline" => LlmClassificationMode::Inline,
+                "deferred" => LlmClassificationMode::Deferred,
+                _ => {
+                    config.new_build_error(
+                        "spam-filter.llm.mode",
+                        "Invalid classification mode, expected \"inline\" or \"deferred\"",
+                    );
+                    return None;
+                }
+            },
+            deferred_junk_score: config
+                .property_or_default("spam-filter.llm.deferred.junk-score", "5.0")
+                .unwrap_or(5.0),
+            deferred_queue_size: config
+                .property_or_default("spam-filter.llm.deferred.queue-size", "10000")
+                .unwrap_or(10000),
+            deferred_concurrency: config
+                .property_or_default::<usize>("spam-filter.llm.deferred.concurrency", "4")
+                .unwrap_or(4)
+                .max(1),
         };
 
         if llm.categories.is_empty() {
@@ -258,10 +425,346 @@ impl SpamFilterLlmConfig {
             return None;
         }
 
+        // 解析分类与置信度对应的分数调整
+        llm.scores = parse_llm_scores(config, &llm);
+
         llm.into()
     }
 }
 
+// 为PhishingLlmConfig结构体实现解析方法
+impl PhishingLlmConfig {
+    pub fn parse(
+        config: &mut Config,
+        models: &AHashMap<String, Arc<AiApiConfig>>,
+        pools: &AHashMap<String, Arc<AiApiPool>>,
+    ) -> Option<Self> {
+        if !config
+            .property_or_default::<bool>("spam-filter.llm.phishing.enable", "false")
+            .unwrap_or_default()
+        {
+            return None;
+        }
+
+        PhishingLlmConfig {
+            model: parse_ai_endpoint(config, "spam-filter.llm.phishing.model", models, pools)?,
+            temperature: config
+                .property_or_default("spam-filter.llm.phishing.temperature", "0.0")
+                .unwrap_or(0.0),
+            body_limit: config
+                .property_or_default("spam-filter.llm.phishing.limits.body", "4096")
+                .unwrap_or(4096),
+            score: config
+                .property_or_default("spam-filter.llm.phishing.score", "10.0")
+                .unwrap_or(10.0),
+            min_confidence: config
+                .property_or_default::<f64>("spam-filter.llm.phishing.min-confidence", "0.7")
+                .unwrap_or(0.7)
+                .clamp(0.0, 1.0),
+            header: config
+                .value("spam-filter.llm.phishing.header")
+                .unwrap_or("X-Spam-Phishing")
+                .trim()
+                .to_string(),
+        }
+        .into()
+    }
+}
+
+// 为AiAssistantConfig结构体实现解析方法
+impl AiAssistantConfig {
+    pub fn parse(
+        config: &mut Config,
+        models: &AHashMap<String, Arc<AiApiConfig>>,
+        pools: &AHashMap<String, Arc<AiApiPool>>,
+    ) -> Option<Self> {
+        if !config
+            .property_or_default::<bool>("enterprise.ai-assistant.enable", "false")
+            .unwrap_or_default()
+        {
+            return None;
+        }
+        let model = parse_ai_endpoint(config, "enterprise.ai-assistant.model", models, pools)?;
+        let message = config
+            .value("enterprise.ai-assistant.message")
+            .unwrap_or(DEFAULT_AI_ASSISTANT_MESSAGE)
+            .to_string();
+        let message = parse_llm_template(
+            config,
+            "enterprise.ai-assistant.message",
+            "enterprise.ai-assistant.limits",
+            &message,
+            false,
+        );
+
+        let mut categories = config
+            .values("enterprise.ai-assistant.categories")
+            .map(|(_, v)| v.trim().trim_start_matches('$').to_lowercase())
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>();
+        if categories.is_empty() {
+            categories = DEFAULT_AI_ASSISTANT_CATEGORIES
+                .iter()
+                .map(|c| c.to_string())
+                .collect();
+        }
+
+        AiAssistantConfig {
+            model,
+            temperature: config
+                .property_or_default("enterprise.ai-assistant.temperature", "0.2")
+                .unwrap_or(0.2),
+            message,
+            categories,
+            summary_length: config
+                .property_or_default("enterprise.ai-assistant.summary.max-length", "280")
+                .unwrap_or(280),
+        }
+        .into()
+    }
+}
+
+// 为AiEmbeddingConfig结构体实现解析方法
+impl AiEmbeddingConfig {
+    pub fn parse(
+        config: &mut Config,
+        models: &AHashMap<String, Arc<AiApiConfig>>,
+    ) -> Option<Self> {
+        let model = config.value("enterprise.ai-embedding.model")?;
+        let model = match models.get(model) {
+            Some(model) if matches!(model.api_type, ApiType::Embedding) => model.clone(),
+            Some(_) => {
+                let message = format!("Model {model:?} is not an embedding API");
+                config.new_build_error("enterprise.ai-embedding.model", message);
+                return None;
+            }
+            None => {
+                let message = format!("Model {model:?} not found in AI API configuration");
+                config.new_build_error("enterprise.ai-embedding.model", message);
+                return None;
+            }
+        };
+
+        AiEmbeddingConfig {
+            model,
+            max_input: config
+                .property_or_default("enterprise.ai-embedding.max-input", "8192")
+                .unwrap_or(8192),
+        }
+        .into()
+    }
+}
+
+// 为AiSieveConfig结构体实现解析方法
+impl AiSieveConfig {
+    pub fn parse(
+        config: &mut Config,
+        models: &AHashMap<String, Arc<AiApiConfig>>,
+        pools: &AHashMap<String, Arc<AiApiPool>>,
+    ) -> Option<Self> {
+        if !config
+            .property_or_default::<bool>("enterprise.ai-sieve.enable", "false")
+            .unwrap_or_default()
+        {
+            return None;
+        }
+
+        AiSieveConfig {
+            model: parse_ai_endpoint(config, "enterprise.ai-sieve.model", models, pools)?,
+            temperature: config
+                .property_or_default("enterprise.ai-sieve.temperature", "0.2")
+                .unwrap_or(0.2),
+            max_attempts: config
+                .property_or_default::<usize>("enterprise.ai-sieve.attempts", "3")
+                .unwrap_or(3)
+                .max(1),
+        }
+        .into()
+    }
+}
+
+// 默认的LLM用户消息模板
+const DEFAULT_LLM_MESSAGE: &str = "Subject: %{subject}%\n\n%{body}%";
+
+// AI助手的默认消息模板与分类
+const DEFAULT_AI_ASSISTANT_MESSAGE: &str = "From: %{from}%\nSubject: %{subject}%\n\n%{body}%";
+const DEFAULT_AI_ASSISTANT_CATEGORIES: &[&str] =
+    &["newsletter", "receipt", "social", "promotion", "notification"];
+
+// 根据ID在AI API或AI API池中查找模型，嵌入API不能用于文本生成
+fn parse_ai_endpoint(
+    config: &mut Config,
+    key: &str,
+    models: &AHashMap<String, Arc<AiApiConfig>>,
+    pools: &AHashMap<String, Arc<AiApiPool>>,
+) -> Option<AiEndpoint> {
+    let model = config.value_require_non_empty(key)?;
+    if let Some(api) = models.get(model) {
+        if matches!(api.api_type, ApiType::Embedding) {
+            let message = format!("Model {model:?} is an embedding API");
+            config.new_build_error(key, message);
+            None
+        } else {
+            Some(AiEndpoint::Api(api.clone()))
+        }
+    } else if let Some(pool) = pools.get(model) {
+        Some(AiEndpoint::Pool(pool.clone()))
+    } else {
+        let message = format!("Model {model:?} not found in AI API configuration");
+        config.new_build_error(key, message);
+        None
+    }
+}
+
+// 解析LLM提示模板，变量长度限制从 `<limits_key>.<name>` 读取。
+// 系统提示只允许引用由服务器计算的可信变量（认证结果、标签和分数），
+// 以免消息内容进入指令部分；未知变量视为配置错误
+fn parse_llm_template(
+    config: &mut Config,
+    key: &str,
+    limits_key: &str,
+    value: &str,
+    trusted_only: bool,
+) -> LlmPromptTemplate {
+    let mut tokens = Vec::new();
+    let mut buf = String::new();
+    let mut value = value;
+
+    while let Some(start) = value.find("%{") {
+        buf.push_str(&value[..start]);
+        value = &value[start + 2..];
+
+        let Some((name, rest)) = value.split_once("}%") else {
+            // 没有结束标记，按普通文本处理
+            buf.push_str("%{");
+            continue;
+        };
+        value = rest;
+
+        match LlmPromptVariable::parse(name) {
+            Some(variable) if trusted_only && !variable.is_trusted() => {
+                config.new_build_error(
+                    key,
+                    format!("Prompt variable {name:?} is not allowed in system instructions"),
+                );
+            }
+            Some(variable) => {
+                let limit = config
+                    .property_or_default::<usize>(
+                        (limits_key, name),
+                        variable.default_limit(),
+                    )
+                    .unwrap_or(4096);
+                if !buf.is_empty() {
+                    tokens.push(LlmPromptToken::Text(std::mem::take(&mut buf)));
+                }
+                tokens.push(LlmPromptToken::Variable { variable, limit });
+            }
+            None => {
+                config.new_build_error(key, format!("Unknown prompt variable {name:?}"));
+            }
+        }
+    }
+    buf.push_str(value);
+
+    if !buf.is_empty() {
+        tokens.push(LlmPromptToken::Text(buf));
+    }
+
+    LlmPromptTemplate(tokens)
+}
+
+// 解析LLM分数调整规则，并根据已声明的分类和置信度进行验证
+fn parse_llm_scores(config: &mut Config, llm: &SpamFilterLlmConfig) -> Vec<LlmScoreRule> {
+    let mut rules: Vec<LlmScoreRule> = Vec::new();
+
+    for id in config
+        .sub_keys("spam-filter.llm.score", ".category")
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+    {
+        let id = id.as_str();
+        let Some(category) = config
+            .value_require_non_empty(("spam-filter.llm.score", id, "category"))
+            .map(|v| v.trim().to_uppercase())
+        else {
+            continue;
+        };
+        let confidence = config
+            .value(("spam-filter.llm.score", id, "confidence"))
+            .map(|v| v.trim().to_uppercase())
+            .filter(|v| !v.is_empty() && v != "*");
+        let Some(score) = config.property_require::<f64>(("spam-filter.llm.score", id, "score"))
+        else {
+            continue;
+        };
+        let tags = config
+            .values(("spam-filter.llm.score", id, "tags"))
+            .map(|(_, v)| v.trim().to_uppercase())
+            .filter(|v| !v.is_empty())
+            .collect::<Vec<_>>();
+
+        if !llm.categories.contains(&category) {
+            config.new_build_error(
+                ("spam-filter.llm.score", id, "category"),
+                format!("Category {category:?} is not declared in spam-filter.llm.categories"),
+            );
+        } else if confidence
+            .as_ref()
+            .is_some_and(|confidence| !llm.confidence.contains(confidence))
+        {
+            config.new_build_error(
+                ("spam-filter.llm.score", id, "confidence"),
+                format!(
+                    "Confidence {:?} is not declared in spam-filter.llm.confidence",
+                    confidence.unwrap_or_default()
+                ),
+            );
+        } else if rules
+            .iter()
+            .any(|rule| rule.category == category && rule.confidence == confidence)
+        {
+            config.new_build_error(
+                ("spam-filter.llm.score", id),
+                "Duplicate category and confidence combination",
+            );
+        } else {
+            rules.push(LlmScoreRule {
+                category,
+                confidence,
+                score,
+                tags,
+            });
+        }
+    }
+
+    rules
+}
+
+// 解析LLM少样本示例
+fn parse_llm_examples(config: &mut Config) -> Vec<(String, String)> {
+    let mut examples = Vec::new();
+
+    for id in config
+        .sub_keys("spam-filter.llm.examples", ".message")
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+    {
+        if let (Some(message), Some(response)) = (
+            config
+                .value_require_non_empty(("spam-filter.llm.examples", id.as_str(), "message"))
+                .map(|s| s.to_string()),
+            config
+                .value_require_non_empty(("spam-filter.llm.examples", id.as_str(), "response"))
+                .map(|s| s.to_string()),
+        ) {
+            examples.push((message, response));
+        }
+    }
+
+    examples
+}
+
 // 解析指标警报
 pub fn parse_metric_alerts(config: &mut Config) -> Vec<MetricAlert> {
     let mut alerts = Vec::new();
@@ -455,3 +958,57 @@ fn sanitize_metric_name(name: &str) -> String {
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::Cow;
+
+    use utils::config::Config;
+
+    use super::parse_llm_template;
+    use crate::enterprise::llm::LlmPromptContext;
+
+    #[test]
+    fn llm_template() {
+        let mut config = Config::new("[spam-filter.llm.limits]\nsubject = 4\n").unwrap();
+        let context = LlmPromptContext {
+            subject: Cow::Borrowed("Hello world"),
+            spf: Cow::Borrowed("pass"),
+            ..Default::default()
+        };
+
+        let template = parse_llm_template(
+            &mut config,
+            "message",
+            "spam-filter.llm.limits",
+            "Subject: %{subject}%, SPF: %{spf}%, 100%{ literal",
+            false,
+        );
+        assert_eq!(
+            template.build(&context),
+            "Subject: Hell [truncated], SPF: pass, 100%{ literal"
+        );
+        assert!(config.errors.is_empty());
+
+        // 未知变量
+        parse_llm_template(
+            &mut config,
+            "message",
+            "spam-filter.llm.limits",
+            "%{subjet}%",
+            false,
+        );
+        assert!(config.errors.contains_key("message"));
+
+        // 系统提示只允许可信变量
+        let template = parse_llm_template(
+            &mut config,
+            "prompt",
+            "spam-filter.llm.limits",
+            "SPF: %{spf}% %{body}%",
+            true,
+        );
+        assert!(config.errors.contains_key("prompt"));
+        assert_eq!(template.build(&context), "SPF: pass ");
+    }
+}
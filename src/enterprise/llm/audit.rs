@@ -0,0 +1,317 @@
+use std::{
+    sync::atomic::{AtomicU64, Ordering},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+use store::{
+    write::{key::KeySerializer, BatchBuilder, InMemoryClass, ValueClass},
+    IterateParams, Store, ValueKey, U64_LEN,
+};
+use trc::AddContext;
+use utils::config::Config;
+
+use crate::Server;
+
+use super::{
+    usage::{purge_usage, AiUsage},
+    AiPrompt,
+};
+
+// 跟踪存储中审计记录的键前缀，按时间戳排序以便按保留期清理
+const AUDIT_PREFIX: &[u8] = b"ai-audit.";
+
+// 清理过期记录的间隔
+const PURGE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// 将每个发送到 AI API 的请求记录到跟踪存储
+#[derive(Debug)]
+pub struct AiAuditLog {
+    pub store: Store,
+    pub retention: Option<Duration>,
+    pub content: AiAuditContent,
+    node_id: u64,
+    seq: AtomicU64,
+}
+
+// 审计日志中保留的提示和响应内容
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiAuditContent {
+    // 仅保存 SHA-256 哈希
+    Hash,
+    // 保存实际发送的提示和返回的响应，个人数据由端点的脱敏规则替换
+    Redacted,
+    // 与 Redacted 相同，保留用于兼容旧配置
+    Full,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AiAuditRecord {
+    pub timestamp: u64,
+    pub endpoint: String,
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub feature: Option<String>,
+    #[serde(rename = "tenantId", skip_serializing_if = "Option::is_none", default)]
+    pub tenant_id: Option<u32>,
+    #[serde(rename = "promptHash")]
+    pub prompt_hash: String,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub prompt: Option<String>,
+    #[serde(
+        rename = "responseHash",
+        skip_serializing_if = "Option::is_none",
+        default
+    )]
+    pub response_hash: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub response: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub error: Option<String>,
+    #[serde(rename = "latencyMs")]
+    pub latency_ms: u64,
+    #[serde(rename = "inputTokens", default)]
+    pub input_tokens: u64,
+    #[serde(rename = "outputTokens", default)]
+    pub output_tokens: u64,
+}
+
+impl AiAuditLog {
+    // 写入一条审计记录，prompt 为实际发送的提示，失败只记录日志，不影响请求
+    pub async fn record(
+        &self,
+        endpoint: &str,
+        model: &str,
+        prompt: &AiPrompt,
+        result: Result<&str, &str>,
+        latency: Duration,
+        usage: Option<AiUsage>,
+    ) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_millis() as u64);
+        let prompt_text = prompt_text(prompt);
+        let (response, error) = match result {
+            Ok(response) => (Some(response), None),
+            Err(error) => (None, Some(error.to_string())),
+        };
+        let (prompt_text, response) = match self.content {
+            AiAuditContent::Hash => (None, None),
+            AiAuditContent::Redacted | AiAuditContent::Full => {
+                (Some(prompt_text), response.map(str::to_string))
+            }
+        };
+        let usage = usage.unwrap_or_default();
+
+        let record = AiAuditRecord {
+            timestamp,
+            endpoint: endpoint.to_string(),
+            model: model.to_string(),
+            feature: prompt.feature.map(str::to_string),
+            tenant_id: prompt.tenant_id,
+            prompt_hash: sha256_hex(prompt_text_for_hash(prompt).as_bytes()),
+            prompt: prompt_text,
+            response_hash: result.ok().map(|response| sha256_hex(response.as_bytes())),
+            response,
+            error,
+            latency_ms: latency.as_millis() as u64,
+            input_tokens: usage.input_tokens,
+            output_tokens: usage.output_tokens,
+        };
+
+        let Ok(value) = serde_json::to_vec(&record) else {
+            return;
+        };
+        let mut batch = BatchBuilder::new();
+        batch.set(
+            audit_class(
+                timestamp,
+                Some((self.node_id, self.seq.fetch_add(1, Ordering::Relaxed))),
+            ),
+            value,
+        );
+        if let Err(err) = self.store.write(batch.build()).await {
+            trc::error!(err
+                .caused_by(trc::location!())
+                .details("Failed to write AI audit record"));
+        }
+    }
+
+    // 返回指定毫秒时间范围内的记录，按时间升序
+    pub async fn list(&self, from: u64, to: u64, limit: usize) -> trc::Result<Vec<AiAuditRecord>> {
+        let mut records = Vec::new();
+        self.store
+            .iterate(
+                IterateParams::new(audit_key(from), audit_key(to)).ascending(),
+                |_, value| {
+                    if let Ok(record) = serde_json::from_slice::<AiAuditRecord>(value) {
+                        records.push(record);
+                    }
+                    Ok(records.len() < limit)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+        Ok(records)
+    }
+
+    // 删除超过保留期的记录
+    pub async fn purge(&self) -> trc::Result<()> {
+        if let Some(retention) = self.retention {
+            let cutoff = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |d| d.as_millis() as u64)
+                .saturating_sub(retention.as_millis() as u64);
+            self.store
+                .delete_range(audit_key(0), audit_key(cutoff))
+                .await
+                .caused_by(trc::location!())?;
+        }
+        Ok(())
+    }
+
+    pub fn parse(config: &mut Config, store: Option<&Store>) -> Option<Self> {
+        if !config
+            .property_or_default::<bool>("enterprise.ai-audit.enable", "false")
+            .unwrap_or_default()
+        {
+            return None;
+        }
+        let Some(store) = store else {
+            config.new_build_error(
+                "enterprise.ai-audit.enable",
+                "AI audit log requires tracing history to be enabled",
+            );
+            return None;
+        };
+
+        Some(AiAuditLog {
+            store: store.clone(),
+            retention: config
+                .property_or_default::<Option<Duration>>("enterprise.ai-audit.retention", "90d")
+                .unwrap_or(Some(Duration::from_secs(90 * 24 * 60 * 60))),
+            content: match config
+                .value("enterprise.ai-audit.content")
+                .unwrap_or("hash")
+            {
+                "hash" => AiAuditContent::Hash,
+                "redacted" => AiAuditContent::Redacted,
+                "full" => AiAuditContent::Full,
+                _ => {
+                    config.new_build_error(
+                        "enterprise.ai-audit.content",
+                        "Invalid audit content, expected \"hash\", \"redacted\" or \"full\"",
+                    );
+                    return None;
+                }
+            },
+            node_id: config
+                .property_or_default("cluster.node-id", "0")
+                .unwrap_or(0),
+            seq: AtomicU64::new(0),
+        })
+    }
+}
+
+impl Server {
+    // 列出 AI 审计记录，用于合规审查
+    pub async fn ai_audit_list(
+        &self,
+        from: u64,
+        to: u64,
+        limit: usize,
+    ) -> trc::Result<Vec<AiAuditRecord>> {
+        match self
+            .core
+            .enterprise
+            .as_ref()
+            .and_then(|e| e.ai_audit.as_ref())
+        {
+            Some(audit) => audit.list(from, to, limit).await,
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // 每小时删除过期的审计记录和过去周期的令牌用量计数器，启动时调用一次
+    pub fn spawn_ai_purge(&self) {
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            loop {
+                let server = inner.build_server();
+                if let Err(err) = server.ai_audit_purge().await {
+                    trc::error!(err.details("Failed to purge AI audit log"));
+                }
+                if server.core.enterprise.is_some() {
+                    if let Err(err) = purge_usage(server.store()).await {
+                        trc::error!(err.details("Failed to purge AI token usage counters"));
+                    }
+                }
+                tokio::time::sleep(PURGE_INTERVAL).await;
+            }
+        });
+    }
+
+    pub async fn ai_audit_purge(&self) -> trc::Result<()> {
+        match self
+            .core
+            .enterprise
+            .as_ref()
+            .and_then(|e| e.ai_audit.as_ref())
+        {
+            Some(audit) => audit.purge().await,
+            None => Ok(()),
+        }
+    }
+}
+
+// 键包含节点 ID，避免集群节点在同一毫秒内写入时相互覆盖
+fn audit_class(timestamp: u64, seq: Option<(u64, u64)>) -> ValueClass {
+    let key = KeySerializer::new(AUDIT_PREFIX.len() + U64_LEN * 3)
+        .write(AUDIT_PREFIX)
+        .write(timestamp);
+    ValueClass::InMemory(InMemoryClass::Key(match seq {
+        Some((node_id, seq)) => key.write(node_id).write(seq).finalize(),
+        None => key.finalize(),
+    }))
+}
+
+fn audit_key(timestamp: u64) -> ValueKey<ValueClass> {
+    ValueKey {
+        account_id: 0,
+        collection: 0,
+        document_id: 0,
+        class: audit_class(timestamp, None),
+    }
+}
+
+fn prompt_text(prompt: &AiPrompt) -> String {
+    let mut text = String::new();
+    if let Some(system) = &prompt.system {
+        text.push_str("system: ");
+        text.push_str(system);
+        text.push('\n');
+    }
+    for message in &prompt.messages {
+        text.push_str(&message.role);
+        text.push_str(": ");
+        text.push_str(&message.content);
+        text.push('\n');
+    }
+    text
+}
+
+fn prompt_text_for_hash(prompt: &AiPrompt) -> String {
+    let mut text = prompt_text(prompt);
+    if let Some(schema) = &prompt.response_schema {
+        text.push_str(&schema.schema.to_string());
+    }
+    text
+}
+
+fn sha256_hex(bytes: &[u8]) -> String {
+    ring::digest::digest(&ring::digest::SHA256, bytes)
+        .as_ref()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect()
+}
@@ -0,0 +1,254 @@
+use std::{
+    collections::BTreeMap,
+    time::{Duration, Instant},
+};
+
+use ahash::{AHashMap, AHashSet};
+use parking_lot::Mutex;
+use ring::digest::{Context, SHA256};
+use utils::config::Config;
+
+use super::AiPrompt;
+
+// AI 响应的有界 TTL 缓存，按最近最少使用顺序淘汰
+#[derive(Debug)]
+pub struct AiResponseCache {
+    id: String,
+    features: AHashSet<String>,
+    entries: Mutex<CacheEntries>,
+    max_entries: usize,
+    ttl: Duration,
+}
+
+// 缓存键为完整键材料的SHA-256摘要，查找时比较整个摘要，避免哈希碰撞返回其他租户的响应
+pub type AiCacheKey = [u8; 32];
+
+#[derive(Debug, Default)]
+struct CacheEntries {
+    map: AHashMap<AiCacheKey, CacheEntry>,
+    lru: BTreeMap<u64, AiCacheKey>,
+    seq: u64,
+}
+
+#[derive(Debug)]
+struct CacheEntry {
+    response: String,
+    expires: Instant,
+    seq: u64,
+}
+
+impl AiResponseCache {
+    // 根据模型、租户、账户、功能、提示模板和用户内容指纹生成缓存键，未启用缓存的功能返回 None
+    pub fn key(&self, model: &str, temperature: f64, prompt: &AiPrompt) -> Option<AiCacheKey> {
+        let feature = prompt
+            .feature
+            .filter(|feature| self.features.contains(*feature))?;
+
+        // Every field is length-prefixed so that adjacent fields cannot run into
+        // each other
+        let mut digest = Context::new(&SHA256);
+        let mut write = |bytes: &[u8]| {
+            digest.update(&(bytes.len() as u64).to_be_bytes());
+            digest.update(bytes);
+        };
+        write(model.as_bytes());
+        write(&temperature.to_bits().to_be_bytes());
+        write(feature.as_bytes());
+        write(&prompt.tenant_id.map_or(u64::MAX, u64::from).to_be_bytes());
+        write(&prompt.account_id.map_or(u64::MAX, u64::from).to_be_bytes());
+        write(prompt.system.as_deref().unwrap_or_default().as_bytes());
+        if let Some(schema) = &prompt.response_schema {
+            write(schema.name.as_bytes());
+            write(schema.schema.to_string().as_bytes());
+        }
+        if let Some((content, template)) = prompt.messages.split_last() {
+            for message in template {
+                write(message.role.as_bytes());
+                write(message.content.as_bytes());
+            }
+            write(content.role.as_bytes());
+            write(fingerprint(&content.content).as_bytes());
+        }
+
+        digest.finish().as_ref().try_into().ok()
+    }
+
+    pub fn get(&self, key: AiCacheKey) -> Option<String> {
+        let response = self.entries.lock().get(key, Instant::now());
+
+        if response.is_some() {
+            trc::event!(Ai(trc::AiEvent::CacheHit), Id = self.id.clone());
+        } else {
+            trc::event!(Ai(trc::AiEvent::CacheMiss), Id = self.id.clone());
+        }
+
+        response
+    }
+
+    pub fn insert(&self, key: AiCacheKey, response: String) {
+        self.entries
+            .lock()
+            .insert(key, response, Instant::now() + self.ttl, self.max_entries);
+    }
+
+    pub fn parse(config: &mut Config, id: &str) -> Option<Self> {
+        let max_entries = config
+            .property::<usize>(("enterprise.ai", id, "cache.size"))
+            .filter(|&size| size > 0)?;
+        let mut features = config
+            .values(("enterprise.ai", id, "cache.features"))
+            .map(|(_, feature)| feature.trim().to_string())
+            .collect::<AHashSet<_>>();
+        if features.is_empty() {
+            features.insert("spam-filter".to_string());
+        }
+
+        Some(AiResponseCache {
+            id: id.to_string(),
+            features,
+            entries: Mutex::new(CacheEntries::default()),
+            max_entries,
+            ttl: config
+                .property_or_default(("enterprise.ai", id, "cache.ttl"), "1h")
+                .unwrap_or_else(|| Duration::from_secs(3600)),
+        })
+    }
+}
+
+impl CacheEntries {
+    fn get(&mut self, key: AiCacheKey, now: Instant) -> Option<String> {
+        let entry = self.map.get_mut(&key)?;
+        if entry.expires > now {
+            // Move the entry to the most recently used position
+            self.lru.remove(&entry.seq);
+            entry.seq = self.seq;
+            self.lru.insert(self.seq, key);
+            self.seq += 1;
+            Some(entry.response.clone())
+        } else {
+            self.lru.remove(&entry.seq);
+            self.map.remove(&key);
+            None
+        }
+    }
+
+    fn insert(&mut self, key: AiCacheKey, response: String, expires: Instant, max_entries: usize) {
+        if let Some(entry) = self.map.remove(&key) {
+            self.lru.remove(&entry.seq);
+        }
+        while self.map.len() >= max_entries {
+            match self.lru.pop_first() {
+                Some((_, oldest)) => {
+                    self.map.remove(&oldest);
+                }
+                None => break,
+            }
+        }
+
+        self.map.insert(
+            key,
+            CacheEntry {
+                response,
+                expires,
+                seq: self.seq,
+            },
+        );
+        self.lru.insert(self.seq, key);
+        self.seq += 1;
+    }
+}
+
+// 忽略大小写并合并空白后的内容指纹
+fn fingerprint(content: &str) -> String {
+    let mut result = String::with_capacity(content.len());
+    for word in content.split_whitespace() {
+        result.extend(word.chars().flat_map(char::to_lowercase));
+        result.push(' ');
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use ahash::AHashSet;
+    use parking_lot::Mutex;
+
+    use super::{AiResponseCache, CacheEntries};
+    use crate::enterprise::llm::AiPrompt;
+
+    #[test]
+    fn lru_eviction() {
+        let mut entries = CacheEntries::default();
+        let expires = Instant::now() + Duration::from_secs(60);
+
+        let [one, two, three] = [[1; 32], [2; 32], [3; 32]];
+
+        entries.insert(one, "one".to_string(), expires, 2);
+        entries.insert(two, "two".to_string(), expires, 2);
+        assert_eq!(entries.get(one, Instant::now()).as_deref(), Some("one"));
+
+        // Key 2 is now the least recently used entry
+        entries.insert(three, "three".to_string(), expires, 2);
+        assert_eq!(entries.get(two, Instant::now()), None);
+        assert_eq!(entries.get(one, Instant::now()).as_deref(), Some("one"));
+        assert_eq!(entries.get(three, Instant::now()).as_deref(), Some("three"));
+        assert_eq!(entries.map.len(), entries.lru.len());
+
+        // Expired entries are removed on access
+        assert_eq!(entries.get(one, expires + Duration::from_secs(1)), None);
+        assert_eq!(entries.map.len(), 1);
+        assert_eq!(entries.lru.len(), 1);
+    }
+
+    #[test]
+    fn cache_key() {
+        let cache = AiResponseCache {
+            id: "test".to_string(),
+            features: AHashSet::from_iter(["spam-filter".to_string()]),
+            entries: Mutex::new(CacheEntries::default()),
+            max_entries: 10,
+            ttl: Duration::from_secs(60),
+        };
+        let prompt = |tenant_id, content: &str| {
+            AiPrompt::new()
+                .with_feature("spam-filter")
+                .with_system("Classify")
+                .with_user(content)
+                .with_tenant(tenant_id)
+        };
+        let key = |prompt: &AiPrompt| cache.key("model", 0.0, prompt).unwrap();
+
+        // Copies that only differ in case and wrapping share a key
+        assert_eq!(
+            key(&prompt(Some(1), "Buy  NOW\ncheap pills")),
+            key(&prompt(Some(1), "buy now cheap pills"))
+        );
+
+        // Tenants, models and contents are kept apart
+        assert_ne!(
+            key(&prompt(Some(1), "buy now")),
+            key(&prompt(Some(2), "buy now"))
+        );
+        assert_ne!(
+            key(&prompt(None, "buy now")),
+            key(&prompt(Some(1), "buy now"))
+        );
+        assert_ne!(
+            key(&prompt(Some(1), "buy now")),
+            cache
+                .key("other", 0.0, &prompt(Some(1), "buy now"))
+                .unwrap()
+        );
+        assert_ne!(
+            key(&prompt(Some(1), "buy now")),
+            key(&prompt(Some(1), "buy later"))
+        );
+
+        // Features that did not opt in are not cached
+        assert!(cache
+            .key("model", 0.0, &AiPrompt::new().with_feature("assistant"))
+            .is_none());
+    }
+}
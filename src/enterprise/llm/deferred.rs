@@ -0,0 +1,395 @@
+use std::{future::Future, time::Duration};
+
+use store::{
+    write::{
+        key::{DeserializeBigEndian, KeySerializer},
+        now, BatchBuilder, InMemoryClass, ValueClass,
+    },
+    IterateParams, ValueKey, U32_LEN, U64_LEN,
+};
+use trc::AddContext;
+
+use crate::{enterprise::LlmClassificationMode, Server};
+
+use super::{AiPrompt, LlmVerdict};
+
+const QUEUE_PREFIX: &[u8] = b"ai-deferred.q.";
+const QUEUE_SIZE: &[u8] = b"ai-deferred.size";
+const LEASE_PREFIX: &[u8] = b"ai-deferred.lease.";
+
+// 租约时长，每个时段只有一个节点处理队列
+const LEASE_DURATION: u64 = 60;
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+const MAX_ATTEMPTS: u8 = 5;
+const RETRY_BACKOFF: u64 = 30;
+
+// 等待后台 LLM 分类的已投递邮件
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeferredLlmTask {
+    pub account_id: u32,
+    pub document_id: u32,
+    pub tenant_id: Option<u32>,
+}
+
+// LLM 分类后对邮件执行的操作
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeferredLlmOutcome {
+    pub account_id: u32,
+    pub document_id: u32,
+    pub verdict: LlmVerdict,
+    pub score: f64,
+    pub tags: Vec<String>,
+    pub move_to_junk: bool,
+}
+
+// 访问已投递的邮件，由邮件存储实现
+pub trait DeferredLlmHandler: Clone + Send + Sync + 'static {
+    // 构建队列中邮件的分类提示，邮件已不存在时返回 None
+    fn deferred_llm_prompt(
+        &self,
+        task: &DeferredLlmTask,
+    ) -> impl Future<Output = trc::Result<Option<AiPrompt>>> + Send;
+
+    // 按分类结果将邮件移至垃圾邮件文件夹并添加关键字
+    fn apply_deferred_llm_outcome(
+        &self,
+        outcome: &DeferredLlmOutcome,
+    ) -> impl Future<Output = trc::Result<()>> + Send;
+}
+
+impl Server {
+    // 将已投递的邮件加入后台分类队列，未入队时返回 false，队列已满时邮件不会被分类
+    pub async fn queue_deferred_llm_classification(
+        &self,
+        task: DeferredLlmTask,
+    ) -> trc::Result<bool> {
+        let Some(llm) = self
+            .core
+            .enterprise
+            .as_ref()
+            .and_then(|e| e.spam_filter_llm.as_ref())
+            .filter(|llm| llm.mode == LlmClassificationMode::Deferred)
+        else {
+            return Ok(false);
+        };
+
+        let queued = self
+            .store()
+            .get_counter(queue_size_key())
+            .await
+            .caused_by(trc::location!())?;
+        if queued >= llm.deferred_queue_size as i64 {
+            trc::event!(
+                Ai(trc::AiEvent::ApiError),
+                AccountId = task.account_id,
+                DocumentId = task.document_id,
+                Details = "Deferred LLM queue is full",
+                Total = queued,
+            );
+            return Ok(false);
+        }
+
+        let mut batch = BatchBuilder::new();
+        batch
+            .set(
+                queue_key(now(), task.account_id, task.document_id),
+                task_value(&task, 0),
+            )
+            .add(queue_size_key().class, 1);
+        self.store()
+            .write(batch.build())
+            .await
+            .caused_by(trc::location!())?;
+
+        Ok(true)
+    }
+
+    // 启动队列处理任务，启动时调用一次，只有持有当前时段租约的节点处理队列
+    pub fn spawn_deferred_llm_worker(&self, handler: impl DeferredLlmHandler) {
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            loop {
+                let server = inner.build_server();
+                let period = now() / LEASE_DURATION;
+                match server.acquire_deferred_llm_lease(period).await {
+                    Ok(true) => {
+                        // 持有租约期间定期处理到期任务
+                        while now() / LEASE_DURATION == period {
+                            if let Err(err) = inner
+                                .build_server()
+                                .drain_deferred_llm_queue(&handler)
+                                .await
+                            {
+                                trc::error!(err.details("Failed to process deferred LLM queue"));
+                            }
+                            tokio::time::sleep(POLL_INTERVAL).await;
+                        }
+                        continue;
+                    }
+                    Ok(false) => {}
+                    Err(err) => {
+                        trc::error!(err.details("Failed to acquire deferred LLM queue lease"));
+                    }
+                }
+
+                // 等待下一个时段
+                tokio::time::sleep(Duration::from_secs(
+                    (period + 1) * LEASE_DURATION - now().min((period + 1) * LEASE_DURATION),
+                ))
+                .await;
+            }
+        });
+    }
+
+    // 第一个递增该时段计数器的节点获得租约
+    async fn acquire_deferred_llm_lease(&self, period: u64) -> trc::Result<bool> {
+        let mut batch = BatchBuilder::new();
+        batch.add_and_get(lease_key(period), 1);
+        let acquired = self
+            .store()
+            .write(batch.build())
+            .await
+            .and_then(|ids| ids.last_counter_id())
+            .caused_by(trc::location!())?
+            == 1;
+
+        if acquired {
+            // 删除过去时段的租约
+            self.store()
+                .delete_range(value_key(lease_key(0)), value_key(lease_key(period)))
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        Ok(acquired)
+    }
+
+    async fn drain_deferred_llm_queue(&self, handler: &impl DeferredLlmHandler) -> trc::Result<()> {
+        let period = now() / LEASE_DURATION;
+
+        // 租约到期后停止，避免与下一个持有者重复处理
+        while now() / LEASE_DURATION == period {
+            let Some(concurrency) = self
+                .core
+                .enterprise
+                .as_ref()
+                .and_then(|e| e.spam_filter_llm.as_ref())
+                .map(|llm| llm.deferred_concurrency)
+            else {
+                return Ok(());
+            };
+
+            // 只读取已到期的任务
+            let mut tasks = Vec::with_capacity(concurrency);
+            self.store()
+                .iterate(
+                    IterateParams::new(
+                        value_key(queue_key(0, 0, 0)),
+                        value_key(queue_key(now(), u32::MAX, u32::MAX)),
+                    )
+                    .ascending(),
+                    |key, value| {
+                        let key = key.get(QUEUE_PREFIX.len()..).unwrap_or_default();
+                        tasks.push((
+                            key.deserialize_be_u64(0)?,
+                            value.first().copied().unwrap_or_default(),
+                            DeferredLlmTask {
+                                account_id: key.deserialize_be_u32(U64_LEN)?,
+                                document_id: key.deserialize_be_u32(U64_LEN + U32_LEN)?,
+                                tenant_id: (value.len() > 1)
+                                    .then(|| value.deserialize_be_u32(1))
+                                    .transpose()?,
+                            },
+                        ));
+                        Ok(tasks.len() < concurrency)
+                    },
+                )
+                .await
+                .caused_by(trc::location!())?;
+
+            if tasks.is_empty() {
+                return Ok(());
+            }
+
+            let results = futures::future::join_all(
+                tasks
+                    .iter()
+                    .map(|(_, _, task)| self.classify_deferred_llm_task(*task, handler)),
+            )
+            .await;
+
+            // 成功或邮件已不存在的任务被删除，失败的任务按退避时间重新排队
+            let mut batch = BatchBuilder::new();
+            let mut removed = 0;
+            for ((due, attempts, task), done) in tasks.iter().zip(results) {
+                batch.clear(queue_key(*due, task.account_id, task.document_id));
+                let attempts = attempts.saturating_add(1);
+                if done {
+                    removed += 1;
+                } else if attempts < MAX_ATTEMPTS {
+                    batch.set(
+                        queue_key(
+                            now() + (RETRY_BACKOFF << attempts),
+                            task.account_id,
+                            task.document_id,
+                        ),
+                        task_value(task, attempts),
+                    );
+                } else {
+                    trc::event!(
+                        Ai(trc::AiEvent::ApiError),
+                        AccountId = task.account_id,
+                        DocumentId = task.document_id,
+                        Details = "Deferred LLM classification abandoned",
+                        Total = attempts as u64,
+                    );
+                    removed += 1;
+                }
+            }
+            if removed > 0 {
+                batch.add(queue_size_key().class, -removed);
+            }
+            self.store()
+                .write(batch.build())
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        Ok(())
+    }
+
+    // 返回 false 表示任务应当重试
+    async fn classify_deferred_llm_task(
+        &self,
+        task: DeferredLlmTask,
+        handler: &impl DeferredLlmHandler,
+    ) -> bool {
+        let Some(llm) = self
+            .core
+            .enterprise
+            .as_ref()
+            .and_then(|e| e.spam_filter_llm.as_ref())
+        else {
+            return true;
+        };
+
+        let result: trc::Result<Option<(String, DeferredLlmOutcome)>> = async {
+            let Some(prompt) = handler.deferred_llm_prompt(&task).await? else {
+                return Ok(None);
+            };
+            let Some(endpoint) = self
+                .ai_endpoint_for_tenant(&llm.model, task.tenant_id)
+                .await?
+            else {
+                return Ok(None);
+            };
+            let verdict = endpoint
+                .endpoint
+                .send_request(
+                    prompt.with_tenant(endpoint.tenant_id),
+                    Some(llm.temperature),
+                )
+                .await
+                .and_then(|response| llm.parse_response(&response))?;
+
+            let (score, tags) = llm
+                .score(&verdict)
+                .map(|rule| (rule.score, rule.tags.clone()))
+                .unwrap_or_default();
+            let outcome = DeferredLlmOutcome {
+                account_id: task.account_id,
+                document_id: task.document_id,
+                move_to_junk: score >= llm.deferred_junk_score,
+                verdict,
+                score,
+                tags,
+            };
+
+            // Only log the outcome once it has been applied
+            if outcome.move_to_junk || !outcome.tags.is_empty() {
+                handler
+                    .apply_deferred_llm_outcome(&outcome)
+                    .await
+                    .caused_by(trc::location!())?;
+            }
+
+            Ok(Some((endpoint.endpoint.id().to_string(), outcome)))
+        }
+        .await;
+
+        match result {
+            Ok(Some((id, outcome))) => {
+                trc::event!(
+                    Ai(trc::AiEvent::LlmResponse),
+                    AccountId = outcome.account_id,
+                    DocumentId = outcome.document_id,
+                    Id = id,
+                    Details = "Deferred LLM classification",
+                    Result = outcome.verdict.category,
+                    Total = outcome.score,
+                    Reason = outcome.verdict.explanation,
+                    Contents = if outcome.move_to_junk {
+                        "Moved to Junk"
+                    } else if !outcome.tags.is_empty() {
+                        "Re-tagged"
+                    } else {
+                        "No change"
+                    },
+                );
+                true
+            }
+            Ok(None) => true,
+            Err(err) => {
+                trc::error!(err
+                    .account_id(task.account_id)
+                    .document_id(task.document_id)
+                    .details("Deferred LLM classification failed"));
+                false
+            }
+        }
+    }
+}
+
+fn queue_key(due: u64, account_id: u32, document_id: u32) -> ValueClass {
+    ValueClass::InMemory(InMemoryClass::Key(
+        KeySerializer::new(QUEUE_PREFIX.len() + U64_LEN + U32_LEN * 2)
+            .write(QUEUE_PREFIX)
+            .write(due)
+            .write(account_id)
+            .write(document_id)
+            .finalize(),
+    ))
+}
+
+// 值为尝试次数加上可选的租户 ID
+fn task_value(task: &DeferredLlmTask, attempts: u8) -> Vec<u8> {
+    let mut value = vec![attempts];
+    if let Some(tenant_id) = task.tenant_id {
+        value.extend_from_slice(&tenant_id.to_be_bytes());
+    }
+    value
+}
+
+fn lease_key(period: u64) -> ValueClass {
+    ValueClass::InMemory(InMemoryClass::Counter(
+        KeySerializer::new(LEASE_PREFIX.len() + U64_LEN)
+            .write(LEASE_PREFIX)
+            .write(period)
+            .finalize(),
+    ))
+}
+
+fn queue_size_key() -> ValueKey<ValueClass> {
+    value_key(ValueClass::InMemory(InMemoryClass::Counter(
+        QUEUE_SIZE.to_vec(),
+    )))
+}
+
+fn value_key(class: ValueClass) -> ValueKey<ValueClass> {
+    ValueKey {
+        account_id: 0,
+        collection: 0,
+        document_id: 0,
+        class,
+    }
+}
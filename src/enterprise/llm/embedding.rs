@@ -0,0 +1,421 @@
+use std::borrow::Cow;
+
+use serde::{Deserialize, Serialize};
+use store::{
+    write::{
+        key::{DeserializeBigEndian, KeySerializer},
+        BatchBuilder, InMemoryClass, ValueClass,
+    },
+    BitmapKey, IterateParams, ValueKey, U32_LEN,
+};
+use trc::AddContext;
+
+use crate::Server;
+
+use super::{
+    api_error, usage::AiUsage, AiApiConfig, AiPrompt, AiRequestOptions, AiResponse, ApiFailure,
+    ApiType,
+};
+
+const EMBEDDING_PREFIX: &[u8] = b"ai-embedding.";
+
+#[derive(Serialize, Debug)]
+struct EmbeddingRequest<'x> {
+    model: &'x str,
+    input: &'x [String],
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+    #[serde(default)]
+    model: Option<String>,
+    #[serde(default)]
+    usage: Option<EmbeddingUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingData {
+    #[serde(default)]
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize, Debug)]
+struct EmbeddingUsage {
+    #[serde(default)]
+    prompt_tokens: u64,
+}
+
+impl AiApiConfig {
+    // 通过 /embeddings 接口为每段文本生成向量，与其他请求一样脱敏、限流、重试并计入预算
+    pub async fn embed(
+        &self,
+        texts: &[String],
+        tenant_id: Option<u32>,
+    ) -> trc::Result<Vec<Vec<f32>>> {
+        if !matches!(self.api_type, ApiType::Embedding) {
+            return Err(api_error(
+                &self.id,
+                "Endpoint is not an embedding API".to_string(),
+            ));
+        } else if texts.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        // Placeholders are never restored, the vectors are computed on the redacted text
+        let (prompt, _) = self.redact(
+            texts.iter().fold(
+                AiPrompt::new()
+                    .with_feature("embedding")
+                    .with_tenant(tenant_id),
+                |prompt, text| prompt.with_user(text.as_str()),
+            ),
+        );
+        let texts = prompt
+            .messages
+            .iter()
+            .map(|message| message.content.clone())
+            .collect::<Vec<_>>();
+
+        self.execute(
+            &prompt,
+            AiRequestOptions::default(),
+            || self.post_embeddings(&texts),
+            |vectors| Cow::Owned(format!("{} embeddings", vectors.len())),
+        )
+        .await
+    }
+
+    pub(super) async fn post_embeddings(
+        &self,
+        texts: &[String],
+    ) -> Result<AiResponse<Vec<Vec<f32>>>, ApiFailure> {
+        let body = serde_json::to_string(&EmbeddingRequest {
+            model: &self.model,
+            input: texts,
+        })
+        .map_err(|err| format!("Failed to serialize request: {}", err))?;
+
+        let response = self
+            .client
+            .post(&self.url)
+            .timeout(self.timeout)
+            .headers(self.headers.clone())
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| ApiFailure {
+                message: format!("API request to {} failed: {err}", self.url),
+                retryable: err.is_timeout() || err.is_connect(),
+                retry_after: None,
+            })?;
+
+        if !response.status().is_success() {
+            return Err(self.status_failure(&response));
+        }
+
+        let bytes = response
+            .bytes()
+            .await
+            .map_err(|err| format!("Failed to read response body from {}: {}", self.url, err))?;
+        let mut response = serde_json::from_slice::<EmbeddingResponse>(&bytes).map_err(|err| {
+            format!(
+                "Failed to parse embedding response from {}: {}",
+                self.url, err
+            )
+        })?;
+        if response.data.len() != texts.len() {
+            return Err(format!(
+                "Embedding response from {} contains {} vectors, expected {}",
+                self.url,
+                response.data.len(),
+                texts.len()
+            )
+            .into());
+        }
+        response.data.sort_unstable_by_key(|data| data.index);
+
+        Ok(AiResponse {
+            output: response
+                .data
+                .into_iter()
+                .map(|data| data.embedding)
+                .collect(),
+            model: response.model,
+            usage: response.usage.map(|usage| AiUsage {
+                input_tokens: usage.prompt_tokens,
+                output_tokens: 0,
+            }),
+        })
+    }
+}
+
+impl Server {
+    // 计算并保存文档的向量，替换已有的向量
+    pub async fn ai_index_embedding(
+        &self,
+        account_id: u32,
+        tenant_id: Option<u32>,
+        collection: u8,
+        document_id: u32,
+        text: &str,
+    ) -> trc::Result<()> {
+        let Some(vector) = self.ai_embed(text, tenant_id).await? else {
+            return Ok(());
+        };
+
+        let mut batch = BatchBuilder::new();
+        batch.set(
+            embedding_key(account_id, collection, document_id),
+            serialize_vector(&vector),
+        );
+        self.store()
+            .write(batch.build())
+            .await
+            .caused_by(trc::location!())
+            .map(|_| ())
+    }
+
+    // 文档删除时移除其向量
+    pub async fn ai_delete_embedding(
+        &self,
+        account_id: u32,
+        collection: u8,
+        document_id: u32,
+    ) -> trc::Result<()> {
+        let mut batch = BatchBuilder::new();
+        batch.clear(embedding_key(account_id, collection, document_id));
+        self.store()
+            .write(batch.build())
+            .await
+            .caused_by(trc::location!())
+            .map(|_| ())
+    }
+
+    // 返回与 text 最相似的文档，按相似度降序
+    pub async fn ai_semantic_search(
+        &self,
+        account_id: u32,
+        tenant_id: Option<u32>,
+        collection: u8,
+        text: &str,
+        min_similarity: f32,
+        limit: usize,
+    ) -> trc::Result<Vec<(u32, f32)>> {
+        match self.ai_embed(text, tenant_id).await? {
+            Some(vector) => {
+                self.ai_find_similar(account_id, collection, &vector, min_similarity, limit)
+                    .await
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+
+    // 查找集合中与 vector 相近的向量，跳过并删除已删除文档的向量
+    pub async fn ai_find_similar(
+        &self,
+        account_id: u32,
+        collection: u8,
+        vector: &[f32],
+        min_similarity: f32,
+        limit: usize,
+    ) -> trc::Result<Vec<(u32, f32)>> {
+        let document_ids = self
+            .store()
+            .get_bitmap(BitmapKey::document_ids(account_id, collection))
+            .await
+            .caused_by(trc::location!())?
+            .unwrap_or_default();
+        let mut results = Vec::new();
+        let mut stale = Vec::new();
+
+        self.store()
+            .iterate(
+                IterateParams::new(
+                    value_key(embedding_key(account_id, collection, 0)),
+                    value_key(embedding_key(account_id, collection, u32::MAX)),
+                )
+                .ascending(),
+                |key, value| {
+                    let document_id = key.deserialize_be_u32(key.len() - U32_LEN)?;
+                    if !document_ids.contains(document_id) {
+                        stale.push(document_id);
+                        return Ok(true);
+                    }
+                    let similarity = cosine_similarity(vector, &deserialize_vector(value));
+                    if similarity >= min_similarity {
+                        results.push((document_id, similarity));
+                    }
+                    Ok(true)
+                },
+            )
+            .await
+            .caused_by(trc::location!())?;
+
+        if !stale.is_empty() {
+            let mut batch = BatchBuilder::new();
+            for document_id in stale {
+                batch.clear(embedding_key(account_id, collection, document_id));
+            }
+            self.store()
+                .write(batch.build())
+                .await
+                .caused_by(trc::location!())?;
+        }
+
+        results.sort_unstable_by(|a, b| b.1.total_cmp(&a.1));
+        results.truncate(limit);
+        Ok(results)
+    }
+
+    async fn ai_embed(&self, text: &str, tenant_id: Option<u32>) -> trc::Result<Option<Vec<f32>>> {
+        let Some(config) = self
+            .core
+            .enterprise
+            .as_ref()
+            .and_then(|e| e.ai_embedding.as_ref())
+        else {
+            return Ok(None);
+        };
+
+        // Tenant overrides name chat endpoints, so a tenant that restricts where its
+        // mail is sent gets no embeddings rather than the shared embedding endpoint
+        if self.ai_tenant_policy(tenant_id).await?.is_some() {
+            return Ok(None);
+        }
+
+        let mut end = text.len().min(config.max_input);
+        while !text.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        config
+            .model
+            .embed(&[text[..end].to_string()], tenant_id)
+            .await
+            .map(|vectors| vectors.into_iter().next())
+    }
+}
+
+// 两个向量的余弦相似度，维度不同时为 0
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    if a.len() != b.len() || a.is_empty() {
+        return 0.0;
+    }
+    let (dot, norm_a, norm_b) = a
+        .iter()
+        .zip(b)
+        .fold((0.0f32, 0.0f32, 0.0f32), |(dot, na, nb), (x, y)| {
+            (dot + x * y, na + x * x, nb + y * y)
+        });
+    if norm_a == 0.0 || norm_b == 0.0 {
+        0.0
+    } else {
+        dot / (norm_a.sqrt() * norm_b.sqrt())
+    }
+}
+
+// 按相似度阈值将向量聚类，忽略单个成员的聚类
+pub fn cluster_by_similarity<T: Copy>(vectors: &[(T, Vec<f32>)], threshold: f32) -> Vec<Vec<T>> {
+    let mut clusters: Vec<(&[f32], Vec<T>)> = Vec::new();
+    for (id, vector) in vectors {
+        match clusters
+            .iter_mut()
+            .find(|(leader, _)| cosine_similarity(leader, vector) >= threshold)
+        {
+            Some((_, members)) => members.push(*id),
+            None => clusters.push((vector, vec![*id])),
+        }
+    }
+    clusters
+        .into_iter()
+        .filter(|(_, members)| members.len() > 1)
+        .map(|(_, members)| members)
+        .collect()
+}
+
+fn embedding_key(account_id: u32, collection: u8, document_id: u32) -> ValueClass {
+    ValueClass::InMemory(InMemoryClass::Key(
+        KeySerializer::new(EMBEDDING_PREFIX.len() + U32_LEN * 2 + 1)
+            .write(EMBEDDING_PREFIX)
+            .write(account_id)
+            .write(collection)
+            .write(document_id)
+            .finalize(),
+    ))
+}
+
+fn value_key(class: ValueClass) -> ValueKey<ValueClass> {
+    ValueKey {
+        account_id: 0,
+        collection: 0,
+        document_id: 0,
+        class,
+    }
+}
+
+fn serialize_vector(vector: &[f32]) -> Vec<u8> {
+    vector
+        .iter()
+        .flat_map(|value| value.to_le_bytes())
+        .collect()
+}
+
+fn deserialize_vector(bytes: &[u8]) -> Vec<f32> {
+    bytes
+        .chunks_exact(4)
+        .map(|chunk| f32::from_le_bytes([chunk[0], chunk[1], chunk[2], chunk[3]]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cosine_similarity_values() {
+        for (a, b, expected) in [
+            (vec![1.0, 0.0], vec![1.0, 0.0], 1.0),
+            (vec![1.0, 2.0, 3.0], vec![2.0, 4.0, 6.0], 1.0),
+            (vec![1.0, 0.0], vec![0.0, 1.0], 0.0),
+            (vec![1.0, 0.0], vec![-1.0, 0.0], -1.0),
+            (
+                vec![1.0, 1.0],
+                vec![1.0, 0.0],
+                std::f32::consts::FRAC_1_SQRT_2,
+            ),
+            (vec![1.0, 0.0], vec![1.0, 0.0, 0.0], 0.0),
+            (vec![0.0, 0.0], vec![1.0, 0.0], 0.0),
+            (vec![], vec![], 0.0),
+        ] {
+            let similarity = cosine_similarity(&a, &b);
+            assert!(
+                (similarity - expected).abs() < 1e-6,
+                "{a:?} {b:?}: expected {expected}, got {similarity}"
+            );
+        }
+    }
+
+    #[test]
+    fn clusters() {
+        let vectors = vec![
+            (1, vec![1.0, 0.0, 0.0]),
+            (2, vec![0.0, 1.0, 0.0]),
+            (3, vec![0.99, 0.01, 0.0]),
+            (4, vec![0.0, 0.0, 1.0]),
+            (5, vec![0.01, 0.99, 0.0]),
+        ];
+        assert_eq!(
+            cluster_by_similarity(&vectors, 0.95),
+            vec![vec![1, 3], vec![2, 5]]
+        );
+        assert!(cluster_by_similarity(&vectors, 1.1).is_empty());
+    }
+
+    #[test]
+    fn vector_roundtrip() {
+        let vector = vec![0.5, -1.25, f32::MAX, 0.0];
+        assert_eq!(deserialize_vector(&serialize_vector(&vector)), vector);
+    }
+}
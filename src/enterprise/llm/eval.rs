@@ -0,0 +1,496 @@
+use std::{
+    borrow::Cow,
+    fmt::{Display, Formatter},
+    path::{Path, PathBuf},
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use ahash::AHashMap;
+use mail_parser::{Message, MessageParser};
+use utils::config::Config;
+
+use super::{
+    pool::{AiApiPool, AiEndpoint},
+    tenant::AiTenantPolicy,
+    AiApiConfig, AiRequestOptions, ApiType, ResolvePromptVariable,
+};
+use crate::enterprise::{LlmPromptVariable, SpamFilterLlmConfig};
+
+// 评估语料中的邮件及其期望类别
+#[derive(Debug, Clone)]
+pub struct LabelledMessage {
+    pub path: PathBuf,
+    pub category: String,
+    pub raw: Vec<u8>,
+}
+
+#[derive(Debug, Default)]
+pub struct LlmEvaluationReport {
+    pub total: usize,
+    pub parse_failures: Vec<(PathBuf, String)>,
+    // 无法解析结果的邮件数，按类别统计，计入召回率的漏判
+    pub unclassified: AHashMap<String, usize>,
+    pub api_failures: Vec<(PathBuf, String)>,
+    pub confusion: AHashMap<(String, String), usize>,
+    pub latencies: Vec<Duration>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct CategoryScore {
+    pub precision: f64,
+    pub recall: f64,
+    pub support: usize,
+}
+
+struct EvalMessage<'x> {
+    raw: &'x [u8],
+    message: Message<'x>,
+}
+
+// 加载 <dir>/<CATEGORY>/*.eml 格式的语料
+pub fn load_corpus(dir: &Path) -> std::io::Result<Vec<LabelledMessage>> {
+    let mut corpus = Vec::new();
+
+    for category in std::fs::read_dir(dir)? {
+        let category = category?;
+        if !category.file_type()?.is_dir() {
+            continue;
+        }
+        let name = category.file_name().to_string_lossy().trim().to_uppercase();
+
+        for file in std::fs::read_dir(category.path())? {
+            let path = file?.path();
+            if path
+                .extension()
+                .is_some_and(|ext| ext.eq_ignore_ascii_case("eml"))
+            {
+                corpus.push(LabelledMessage {
+                    raw: std::fs::read(&path)?,
+                    category: name.clone(),
+                    path,
+                });
+            }
+        }
+    }
+
+    corpus.sort_unstable_by(|a, b| a.path.cmp(&b.path));
+    Ok(corpus)
+}
+
+// 读取配置文件和语料后进行评估
+pub async fn evaluate_from_path(
+    config_path: &Path,
+    corpus_path: &Path,
+    api_id: Option<&str>,
+    tenant: Option<&str>,
+) -> Result<LlmEvaluationReport, String> {
+    let mut config = std::fs::read_to_string(config_path)
+        .map_err(|err| format!("Failed to read {}: {err}", config_path.display()))
+        .and_then(|contents| {
+            Config::new(contents)
+                .map_err(|err| format!("Failed to parse {}: {err}", config_path.display()))
+        })?;
+    let corpus = load_corpus(corpus_path).map_err(|err| {
+        format!(
+            "Failed to load corpus from {}: {err}",
+            corpus_path.display()
+        )
+    })?;
+    if corpus.is_empty() {
+        return Err(format!(
+            "No labelled messages found in {}",
+            corpus_path.display()
+        ));
+    }
+
+    evaluate_from_config(&mut config, &corpus, api_id, tenant).await
+}
+
+// 根据配置构建垃圾邮件过滤 LLM 设置并回放语料，可指定 API 和租户策略
+pub async fn evaluate_from_config(
+    config: &mut Config,
+    corpus: &[LabelledMessage],
+    api_id: Option<&str>,
+    tenant: Option<&str>,
+) -> Result<LlmEvaluationReport, String> {
+    let mut apis = AHashMap::new();
+    for id in config
+        .sub_keys("enterprise.ai", ".url")
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+    {
+        if let Some(api) = AiApiConfig::parse(config, &id) {
+            apis.insert(id, Arc::new(api));
+        }
+    }
+    let mut pools = AHashMap::new();
+    for id in config
+        .sub_keys("enterprise.ai-pool", "")
+        .map(|s| s.to_string())
+        .collect::<Vec<_>>()
+    {
+        if let Some(pool) = AiApiPool::parse(config, &id, &apis) {
+            pools.insert(id, Arc::new(pool));
+        }
+    }
+
+    let mut llm = SpamFilterLlmConfig::parse(config, &apis, &pools)
+        .ok_or_else(|| "Spam filter LLM is disabled or its configuration is invalid".to_string())?;
+    if let Some(api_id) = api_id {
+        if apis
+            .get(api_id)
+            .is_some_and(|api| matches!(api.api_type, ApiType::Embedding))
+        {
+            return Err(format!("AI API {api_id:?} is an embedding API"));
+        }
+        llm.model = apis
+            .get(api_id)
+            .cloned()
+            .map(AiEndpoint::Api)
+            .or_else(|| pools.get(api_id).cloned().map(AiEndpoint::Pool))
+            .ok_or_else(|| format!("AI API {api_id:?} not found"))?;
+    }
+    if let Some(tenant) = tenant {
+        match AiTenantPolicy::parse(config, tenant, &apis, &pools) {
+            Some(AiTenantPolicy::Disable) => {
+                return Err(format!("AI features are disabled for tenant {tenant:?}"));
+            }
+            Some(AiTenantPolicy::Override(endpoint)) => llm.model = endpoint,
+            None => (),
+        }
+    }
+    if let Some((key, err)) = config.errors.iter().next() {
+        return Err(format!("Invalid configuration at {key}: {err:?}"));
+    }
+
+    Ok(llm.evaluate(corpus).await)
+}
+
+impl SpamFilterLlmConfig {
+    pub async fn evaluate(&self, corpus: &[LabelledMessage]) -> LlmEvaluationReport {
+        let mut report = LlmEvaluationReport::default();
+
+        for item in corpus {
+            report.total += 1;
+            let Some(message) = MessageParser::default().parse(&item.raw) else {
+                report.parse_failure(item, "Failed to parse message".to_string());
+                continue;
+            };
+            let prompt = self.conversation(
+                &EvalMessage {
+                    raw: &item.raw,
+                    message,
+                },
+                None,
+            );
+
+            // Replays must reach the model every time and must not use up the
+            // production token budget
+            let time = Instant::now();
+            let response = self
+                .model
+                .send_request_with(
+                    prompt,
+                    Some(self.temperature),
+                    AiRequestOptions {
+                        cache: false,
+                        budget: false,
+                        ..Default::default()
+                    },
+                )
+                .await;
+            report.latencies.push(time.elapsed());
+
+            match response {
+                Ok(response) => match self.parse_response(&response) {
+                    Ok(verdict) => {
+                        *report
+                            .confusion
+                            .entry((item.category.clone(), verdict.category))
+                            .or_default() += 1;
+                    }
+                    Err(err) => {
+                        report.parse_failure(item, err.to_string());
+                    }
+                },
+                Err(err) => {
+                    report
+                        .api_failures
+                        .push((item.path.clone(), err.to_string()));
+                }
+            }
+        }
+
+        report
+    }
+}
+
+impl LlmEvaluationReport {
+    fn parse_failure(&mut self, item: &LabelledMessage, reason: String) {
+        self.parse_failures.push((item.path.clone(), reason));
+        *self.unclassified.entry(item.category.clone()).or_default() += 1;
+    }
+
+    pub fn categories(&self) -> Vec<&str> {
+        let mut categories = self
+            .confusion
+            .keys()
+            .flat_map(|(expected, predicted)| [expected.as_str(), predicted.as_str()])
+            .chain(self.unclassified.keys().map(|category| category.as_str()))
+            .collect::<Vec<_>>();
+        categories.sort_unstable();
+        categories.dedup();
+        categories
+    }
+
+    pub fn category_score(&self, category: &str) -> CategoryScore {
+        let mut true_positives = 0;
+        let mut predicted = 0;
+        let mut support = self.unclassified.get(category).copied().unwrap_or_default();
+
+        for ((expected_cat, predicted_cat), count) in &self.confusion {
+            if expected_cat == category {
+                support += count;
+                if predicted_cat == category {
+                    true_positives += count;
+                }
+            }
+            if predicted_cat == category {
+                predicted += count;
+            }
+        }
+
+        CategoryScore {
+            precision: ratio(true_positives, predicted),
+            recall: ratio(true_positives, support),
+            support,
+        }
+    }
+
+    pub fn latency_percentile(&self, percentile: f64) -> Duration {
+        let mut latencies = self.latencies.clone();
+        latencies.sort_unstable();
+        latencies
+            .get(((latencies.len() as f64 * percentile).ceil() as usize).saturating_sub(1))
+            .copied()
+            .unwrap_or_default()
+    }
+}
+
+impl Display for LlmEvaluationReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let categories = self.categories();
+
+        writeln!(f, "Messages evaluated: {}", self.total)?;
+        writeln!(f, "API failures: {}", self.api_failures.len())?;
+        writeln!(f, "Parse failures: {}", self.parse_failures.len())?;
+        writeln!(
+            f,
+            "Latency p50: {:?}, p95: {:?}, max: {:?}",
+            self.latency_percentile(0.5),
+            self.latency_percentile(0.95),
+            self.latency_percentile(1.0)
+        )?;
+
+        writeln!(
+            f,
+            "\n{:<20} {:>9} {:>9} {:>9}",
+            "Category", "Precision", "Recall", "Support"
+        )?;
+        for category in &categories {
+            let score = self.category_score(category);
+            writeln!(
+                f,
+                "{:<20} {:>9.3} {:>9.3} {:>9}",
+                category, score.precision, score.recall, score.support
+            )?;
+        }
+
+        write!(
+            f,
+            "\nConfusion matrix (rows: expected, columns: predicted)\n{:<20}",
+            ""
+        )?;
+        for category in &categories {
+            write!(f, " {:>12}", category)?;
+        }
+        writeln!(f)?;
+        for expected in &categories {
+            write!(f, "{:<20}", expected)?;
+            for predicted in &categories {
+                write!(
+                    f,
+                    " {:>12}",
+                    self.confusion
+                        .get(&(expected.to_string(), predicted.to_string()))
+                        .copied()
+                        .unwrap_or_default()
+                )?;
+            }
+            writeln!(f)?;
+        }
+
+        for (path, err) in self.api_failures.iter().chain(self.parse_failures.iter()) {
+            writeln!(f, "{}: {}", path.display(), err)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl ResolvePromptVariable for EvalMessage<'_> {
+    fn resolve_prompt_variable(&self, variable: LlmPromptVariable) -> Cow<'_, str> {
+        match variable {
+            LlmPromptVariable::Subject => self.message.subject().unwrap_or_default().into(),
+            LlmPromptVariable::From => self
+                .message
+                .from()
+                .and_then(|from| from.first())
+                .map(|addr| {
+                    match (addr.name(), addr.address()) {
+                        (Some(name), Some(address)) => format!("{name} <{address}>"),
+                        (None, Some(address)) => address.to_string(),
+                        (Some(name), None) => name.to_string(),
+                        (None, None) => String::new(),
+                    }
+                    .into()
+                })
+                .unwrap_or_default(),
+            LlmPromptVariable::Sender => self
+                .message
+                .header_raw("Return-Path")
+                .unwrap_or_default()
+                .trim()
+                .trim_start_matches('<')
+                .trim_end_matches('>')
+                .into(),
+            LlmPromptVariable::To => self
+                .message
+                .to()
+                .map(|to| {
+                    to.iter()
+                        .filter_map(|addr| addr.address())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                        .into()
+                })
+                .unwrap_or_default(),
+            LlmPromptVariable::Headers => {
+                let end = self
+                    .raw
+                    .windows(4)
+                    .position(|w| w == b"\r\n\r\n")
+                    .or_else(|| self.raw.windows(2).position(|w| w == b"\n\n"))
+                    .unwrap_or(self.raw.len());
+                String::from_utf8_lossy(&self.raw[..end])
+            }
+            LlmPromptVariable::Body => self.message.body_text(0).unwrap_or_default(),
+            LlmPromptVariable::Spf => self.auth_result("spf"),
+            LlmPromptVariable::Dkim => self.auth_result("dkim"),
+            LlmPromptVariable::Dmarc => self.auth_result("dmarc"),
+            LlmPromptVariable::Tags | LlmPromptVariable::Score => "".into(),
+        }
+    }
+}
+
+impl EvalMessage<'_> {
+    fn auth_result(&self, method: &str) -> Cow<'_, str> {
+        self.message
+            .header_raw("Authentication-Results")
+            .and_then(|results| {
+                results.split(';').find_map(|result| {
+                    result
+                        .trim()
+                        .strip_prefix(method)
+                        .and_then(|result| result.strip_prefix('='))
+                        .and_then(|result| result.split_whitespace().next())
+                })
+            })
+            .unwrap_or("none")
+            .into()
+    }
+}
+
+fn ratio(value: usize, total: usize) -> f64 {
+    if total > 0 {
+        value as f64 / total as f64
+    } else {
+        0.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use utils::config::Config;
+
+    use super::{evaluate_from_config, LabelledMessage, LlmEvaluationReport};
+    use crate::enterprise::llm::mock::spawn_mock_endpoint;
+
+    #[test]
+    fn parse_failures_count_as_misses() {
+        let mut report = LlmEvaluationReport::default();
+        report
+            .confusion
+            .insert(("SPAM".to_string(), "SPAM".to_string()), 3);
+        report.unclassified.insert("SPAM".to_string(), 1);
+
+        let score = report.category_score("SPAM");
+        assert_eq!(score.support, 4);
+        assert_eq!(score.precision, 1.0);
+        assert_eq!(score.recall, 0.75);
+    }
+
+    #[tokio::test]
+    async fn evaluate_mock_endpoint() {
+        let url = spawn_mock_endpoint(|request| {
+            if request
+                .messages
+                .iter()
+                .any(|m| m.content.contains("lottery"))
+            {
+                "SPAM".to_string()
+            } else {
+                "oops".to_string()
+            }
+        })
+        .await
+        .unwrap();
+        let mut config = Config::new(format!(
+            concat!(
+                "[enterprise.ai.mock]\n",
+                "url = \"{}\"\n",
+                "type = \"chat\"\n",
+                "model = \"mock\"\n",
+                "[spam-filter.llm]\n",
+                "enable = true\n",
+                "model = \"mock\"\n",
+                "prompt = \"Classify the message\"\n",
+                "categories = [\"SPAM\", \"HAM\"]\n",
+            ),
+            url
+        ))
+        .unwrap();
+        let corpus = [("SPAM", "You won the lottery"), ("HAM", "Meeting at noon")]
+            .into_iter()
+            .map(|(category, subject)| LabelledMessage {
+                path: PathBuf::from(format!("{category}.eml")),
+                category: category.to_string(),
+                raw: format!("From: a@example.org\r\nSubject: {subject}\r\n\r\nHello\r\n")
+                    .into_bytes(),
+            })
+            .collect::<Vec<_>>();
+
+        let report = evaluate_from_config(&mut config, &corpus, None, None)
+            .await
+            .unwrap();
+        assert_eq!(report.total, 2);
+        assert!(report.api_failures.is_empty());
+        assert_eq!(report.parse_failures.len(), 1);
+        assert_eq!(report.category_score("SPAM").recall, 1.0);
+        assert_eq!(report.category_score("HAM").recall, 0.0);
+        assert_eq!(report.category_score("HAM").support, 1);
+    }
+}
@@ -0,0 +1,115 @@
+use crate::{
+    enterprise::SpamFilterLlmConfig,
+    expr::{functions::ResolveVariable, Variable},
+    Server,
+};
+
+use super::{tenant::AiTenantEndpoint, LlmVerdict, ResolvePromptVariable};
+
+pub const V_LLM_SCORE: u32 = 0;
+pub const V_LLM_IS_AUTHENTICATED: u32 = 1;
+pub const V_LLM_SPF: u32 = 2;
+pub const V_LLM_DKIM: u32 = 3;
+pub const V_LLM_DMARC: u32 = 4;
+pub const V_LLM_SENDER_DOMAIN: u32 = 5;
+pub const V_LLM_TAG_COUNT: u32 = 6;
+
+pub const LLM_GATE_VARIABLES: &[(&str, u32)] = &[
+    ("score", V_LLM_SCORE),
+    ("is_authenticated", V_LLM_IS_AUTHENTICATED),
+    ("spf", V_LLM_SPF),
+    ("dkim", V_LLM_DKIM),
+    ("dmarc", V_LLM_DMARC),
+    ("sender_domain", V_LLM_SENDER_DOMAIN),
+    ("tag_count", V_LLM_TAG_COUNT),
+];
+
+// spam-filter.llm.condition 可用的预评分和认证结果
+#[derive(Debug, Clone, Default)]
+pub struct LlmGateContext<'x> {
+    pub score: f64,
+    pub is_authenticated: bool,
+    pub spf: &'x str,
+    pub dkim: &'x str,
+    pub dmarc: &'x str,
+    pub sender_domain: &'x str,
+    // DMARC 认证的 From 地址域名
+    pub from_domain: &'x str,
+    pub tag_count: usize,
+}
+
+impl Server {
+    // 在发送请求前判断邮件是否由 LLM 分类，返回使用的端点，不应发送时返回 None
+    pub async fn spam_filter_llm_eligible(
+        &self,
+        llm: &SpamFilterLlmConfig,
+        ctx: &LlmGateContext<'_>,
+        rcpt_domains: &[&str],
+        session_id: u64,
+    ) -> trc::Result<Option<AiTenantEndpoint>> {
+        if !llm.exclude_domains.is_empty() && ctx.dmarc.eq_ignore_ascii_case("pass") {
+            let from_domain = ctx.from_domain.to_lowercase();
+            if llm.exclude_domains.iter().any(|domain| {
+                from_domain == *domain
+                    || from_domain
+                        .strip_suffix(domain.as_str())
+                        .is_some_and(|prefix| prefix.ends_with('.'))
+            }) {
+                return Ok(None);
+            }
+        }
+
+        if llm.sample_rate < 100.0 && rand::random::<f64>() * 100.0 >= llm.sample_rate {
+            return Ok(None);
+        }
+
+        if let Some(condition) = &llm.condition {
+            if !self
+                .eval_expr(condition, ctx, "spam-filter.llm.condition", session_id)
+                .await
+                .unwrap_or(false)
+            {
+                return Ok(None);
+            }
+        }
+
+        self.ai_endpoint_for_domains(&llm.model, rcpt_domains.iter().copied())
+            .await
+    }
+
+    // 使用 spam_filter_llm_eligible 返回的端点分类邮件，并计入其租户
+    pub async fn spam_filter_llm_classify(
+        &self,
+        llm: &SpamFilterLlmConfig,
+        endpoint: &AiTenantEndpoint,
+        message: &impl ResolvePromptVariable,
+    ) -> trc::Result<LlmVerdict> {
+        let response = endpoint
+            .endpoint
+            .send_request(
+                llm.conversation(message, endpoint.tenant_id),
+                Some(llm.temperature),
+            )
+            .await?;
+        llm.parse_response(&response)
+    }
+}
+
+impl ResolveVariable for LlmGateContext<'_> {
+    fn resolve_variable(&self, variable: u32) -> Variable<'_> {
+        match variable {
+            V_LLM_SCORE => Variable::Float(self.score),
+            V_LLM_IS_AUTHENTICATED => Variable::Integer(self.is_authenticated as i64),
+            V_LLM_SPF => self.spf.into(),
+            V_LLM_DKIM => self.dkim.into(),
+            V_LLM_DMARC => self.dmarc.into(),
+            V_LLM_SENDER_DOMAIN => self.sender_domain.into(),
+            V_LLM_TAG_COUNT => Variable::Integer(self.tag_count as i64),
+            _ => Variable::Integer(0),
+        }
+    }
+
+    fn resolve_global(&self, _: &str) -> Variable<'_> {
+        Variable::Integer(0)
+    }
+}
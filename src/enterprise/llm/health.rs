@@ -0,0 +1,360 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use serde::Serialize;
+use store::write::now;
+use utils::config::Config;
+
+use crate::Server;
+
+use super::{AiApiConfig, AiPrompt, ApiType};
+
+const MAX_LATENCY_SAMPLES: usize = 64;
+const DEFAULT_CHECK_TICK: Duration = Duration::from_secs(60);
+
+// AI 端点最近请求和探测的结果
+#[derive(Debug, Default)]
+pub struct AiApiHealth {
+    pub interval: Option<Duration>,
+    pub startup_check: bool,
+    state: Mutex<AiHealthState>,
+}
+
+#[derive(Debug, Default)]
+struct AiHealthState {
+    healthy: Option<bool>,
+    last_error: Option<String>,
+    last_error_at: Option<u64>,
+    last_success_at: Option<u64>,
+    last_probe: Option<Instant>,
+    echoed_model: Option<String>,
+    latencies: VecDeque<u64>,
+    successes: u64,
+    failures: u64,
+    consecutive_failures: u32,
+    open_until: u64,
+    probe_started: u64,
+}
+
+// 熔断器状态：冷却期结束后进入半开状态，只放行一个探测请求
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub enum AiCircuitState {
+    Closed,
+    Open,
+    HalfOpen,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct AiApiStatus {
+    pub id: String,
+    pub model: String,
+    #[serde(rename = "echoedModel")]
+    pub echoed_model: Option<String>,
+    pub healthy: Option<bool>,
+    #[serde(rename = "lastError")]
+    pub last_error: Option<String>,
+    #[serde(rename = "lastErrorAt")]
+    pub last_error_at: Option<u64>,
+    #[serde(rename = "lastSuccessAt")]
+    pub last_success_at: Option<u64>,
+    #[serde(rename = "medianLatencyMs")]
+    pub median_latency_ms: Option<u64>,
+    pub successes: u64,
+    pub failures: u64,
+    pub circuit: AiCircuitState,
+}
+
+impl AiApiHealth {
+    pub fn record_success(&self, latency: Duration, model: Option<&str>) {
+        let mut state = self.state.lock();
+        state.healthy = Some(true);
+        state.last_success_at = Some(now());
+        state.successes += 1;
+        state.consecutive_failures = 0;
+        state.open_until = 0;
+        state.probe_started = 0;
+        if let Some(model) = model.filter(|model| !model.is_empty()) {
+            if state.echoed_model.as_deref() != Some(model) {
+                state.echoed_model = Some(model.to_string());
+            }
+        }
+        if state.latencies.len() == MAX_LATENCY_SAMPLES {
+            state.latencies.pop_front();
+        }
+        state.latencies.push_back(latency.as_millis() as u64);
+    }
+
+    pub fn record_failure(&self, error: &str) {
+        let mut state = self.state.lock();
+        state.healthy = Some(false);
+        state.last_error = Some(error.to_string());
+        state.last_error_at = Some(now());
+        state.failures += 1;
+        state.consecutive_failures += 1;
+    }
+
+    pub fn circuit_state(&self, now: u64) -> AiCircuitState {
+        AiCircuitState::new(self.state.lock().open_until, now)
+    }
+
+    // 获取半开状态下唯一的探测请求，冷却期内未返回结果的探测视为丢失
+    pub fn try_probe(&self, now: u64, cooldown: Duration) -> bool {
+        let mut state = self.state.lock();
+        if state.probe_started == 0 || state.probe_started + cooldown.as_secs().max(1) <= now {
+            state.probe_started = now;
+            true
+        } else {
+            false
+        }
+    }
+
+    // 连续失败达到阈值，或熔断器打开或半开时再次失败，则打开熔断器；返回是否由本次调用打开
+    pub fn trip(&self, threshold: u32, cooldown: Duration) -> bool {
+        let mut state = self.state.lock();
+        if state.open_until != 0 || state.consecutive_failures >= threshold.max(1) {
+            state.consecutive_failures = 0;
+            state.open_until = now() + cooldown.as_secs().max(1);
+            state.probe_started = 0;
+            true
+        } else {
+            false
+        }
+    }
+
+    // 检查间隔已过或 force 时标记探测开始并返回 true
+    fn start_probe(&self, force: bool) -> bool {
+        let mut state = self.state.lock();
+        let due = force
+            || self.interval.is_some_and(|interval| {
+                state
+                    .last_probe
+                    .is_none_or(|last_probe| last_probe.elapsed() >= interval)
+            });
+        if due {
+            state.last_probe = Some(Instant::now());
+        }
+        due
+    }
+
+    pub fn parse(config: &mut Config, id: &str) -> Self {
+        AiApiHealth {
+            interval: config
+                .property_or_default::<Option<Duration>>(
+                    ("enterprise.ai", id, "health.interval"),
+                    "false",
+                )
+                .unwrap_or_default(),
+            startup_check: config
+                .property_or_default(("enterprise.ai", id, "health.startup-check"), "false")
+                .unwrap_or_default(),
+            state: Mutex::new(AiHealthState::default()),
+        }
+    }
+}
+
+impl AiApiConfig {
+    // 绕过缓存、预算和限流发送最小请求，并记录健康状态和审计日志
+    pub async fn probe(&self) -> trc::Result<()> {
+        let start = Instant::now();
+        let (prompt, result) = if matches!(self.api_type, ApiType::Embedding) {
+            let prompt = AiPrompt::new().with_feature("health-check").with_user("OK");
+            let result = self
+                .post_embeddings(&["OK".to_string()])
+                .await
+                .map(|response| (response.model, "1 embeddings".to_string(), response.usage));
+            (prompt, result)
+        } else {
+            let prompt = AiPrompt::new()
+                .with_feature("health-check")
+                .with_system("This is a health check.")
+                .with_user("Reply with the single word OK.");
+            let result = self
+                .post_api(prompt.clone(), Some(0.0))
+                .await
+                .map(|response| (response.model, response.output, response.usage));
+            (prompt, result)
+        };
+
+        if let Some(audit) = &self.audit {
+            audit
+                .record(
+                    &self.id,
+                    &self.model,
+                    &prompt,
+                    result
+                        .as_ref()
+                        .map(|(_, response, _)| response.as_str())
+                        .map_err(|err| err.message.as_str()),
+                    start.elapsed(),
+                    result.as_ref().ok().and_then(|(_, _, usage)| *usage),
+                )
+                .await;
+        }
+
+        match result {
+            Ok((model, _, _)) => {
+                self.health
+                    .record_success(start.elapsed(), model.as_deref());
+                Ok(())
+            }
+            Err(err) => {
+                self.health.record_failure(&err.message);
+                Err(super::api_error(&self.id, err.message))
+            }
+        }
+    }
+
+    pub fn status(&self) -> AiApiStatus {
+        let state = self.health.state.lock();
+        let mut latencies = state.latencies.iter().copied().collect::<Vec<_>>();
+        latencies.sort_unstable();
+
+        AiApiStatus {
+            id: self.id.clone(),
+            model: self.model.clone(),
+            echoed_model: state.echoed_model.clone(),
+            healthy: state.healthy,
+            last_error: state.last_error.clone(),
+            last_error_at: state.last_error_at,
+            last_success_at: state.last_success_at,
+            median_latency_ms: latencies.get(latencies.len() / 2).copied(),
+            successes: state.successes,
+            failures: state.failures,
+            circuit: AiCircuitState::new(state.open_until, now()),
+        }
+    }
+}
+
+impl AiCircuitState {
+    fn new(open_until: u64, now: u64) -> Self {
+        match open_until {
+            0 => AiCircuitState::Closed,
+            open_until if now < open_until => AiCircuitState::Open,
+            _ => AiCircuitState::HalfOpen,
+        }
+    }
+}
+
+impl Server {
+    // 启动时验证端点，之后按 health.interval 定期探测，启动时调用一次
+    pub fn spawn_ai_health_checks(&self) {
+        let inner = self.inner.clone();
+        tokio::spawn(async move {
+            inner.build_server().ai_health_check(true).await;
+
+            loop {
+                let tick = inner
+                    .build_server()
+                    .core
+                    .enterprise
+                    .as_ref()
+                    .and_then(|e| {
+                        e.ai_apis
+                            .values()
+                            .filter_map(|api| api.health.interval)
+                            .min()
+                    })
+                    .unwrap_or(DEFAULT_CHECK_TICK)
+                    .clamp(Duration::from_secs(1), DEFAULT_CHECK_TICK);
+                tokio::time::sleep(tick).await;
+                inner.build_server().ai_health_check(false).await;
+            }
+        });
+    }
+
+    // 探测检查间隔已过的端点，startup 时探测所有启用启动检查的端点
+    pub async fn ai_health_check(&self, startup: bool) {
+        let Some(enterprise) = &self.core.enterprise else {
+            return;
+        };
+
+        let probes = enterprise
+            .ai_apis
+            .values()
+            .filter(|api| api.health.start_probe(startup && api.health.startup_check))
+            .map(|api| async move {
+                let result = api.probe().await;
+                (api, result)
+            })
+            .collect::<Vec<_>>();
+
+        for (api, result) in futures::future::join_all(probes).await {
+            match result {
+                Ok(()) => trc::event!(
+                    Ai(trc::AiEvent::LlmResponse),
+                    Id = api.id.clone(),
+                    Details = "AI API health check succeeded",
+                ),
+                Err(err) => trc::error!(err.details(if startup {
+                    "AI API failed startup validation"
+                } else {
+                    "AI API health check failed"
+                })),
+            }
+        }
+    }
+
+    // 所有已配置 AI 端点的状态，按 ID 排序
+    pub fn ai_api_status(&self) -> Vec<AiApiStatus> {
+        let mut status = self
+            .core
+            .enterprise
+            .as_ref()
+            .map(|e| {
+                e.ai_apis
+                    .values()
+                    .map(|api| api.status())
+                    .collect::<Vec<_>>()
+            })
+            .unwrap_or_default();
+        status.sort_unstable_by(|a, b| a.id.cmp(&b.id));
+        status
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{AiApiHealth, AiCircuitState};
+
+    #[test]
+    fn circuit_breaker() {
+        let health = AiApiHealth::default();
+        let cooldown = Duration::from_secs(30);
+        assert_eq!(health.circuit_state(0), AiCircuitState::Closed);
+
+        // Opens after reaching the threshold
+        health.record_failure("error");
+        assert!(!health.trip(2, cooldown));
+        health.record_failure("error");
+        assert!(health.trip(2, cooldown));
+        let now = store::write::now();
+        assert_eq!(health.circuit_state(now), AiCircuitState::Open);
+
+        // Half-open after the cooldown, with a single probe allowed
+        assert_eq!(health.circuit_state(now + 60), AiCircuitState::HalfOpen);
+        assert!(health.try_probe(now + 60, cooldown));
+        assert!(!health.try_probe(now + 60, cooldown));
+        assert!(health.try_probe(now + 90, cooldown));
+
+        // A failed probe reopens the circuit immediately
+        health.record_failure("error");
+        assert!(health.trip(2, cooldown));
+        assert_eq!(health.circuit_state(now), AiCircuitState::Open);
+        assert!(health.try_probe(now + 60, cooldown));
+
+        // A success, including a health check probe, closes it
+        health.record_success(Duration::from_millis(10), None);
+        assert_eq!(health.circuit_state(now), AiCircuitState::Closed);
+
+        // Failed health checks count towards the threshold
+        health.record_failure("error");
+        health.record_failure("error");
+        assert!(health.trip(3, cooldown));
+    }
+}
@@ -0,0 +1,176 @@
+use std::{
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use parking_lot::Mutex;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use utils::config::{Config, Rate};
+
+// AI API 临时故障的重试策略
+#[derive(Debug, Clone, Copy)]
+pub struct AiRetryPolicy {
+    pub max_retries: u32,
+    pub initial_backoff: Duration,
+    pub max_backoff: Duration,
+}
+
+// 限制 AI 端点的并发请求数和请求速率
+#[derive(Debug, Default)]
+pub struct AiRateLimiter {
+    concurrency: Option<Arc<Semaphore>>,
+    bucket: Option<Mutex<TokenBucket>>,
+}
+
+#[derive(Debug)]
+struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    refill_per_sec: f64,
+    last_refill: Instant,
+}
+
+impl AiRetryPolicy {
+    // 带抖动的指数退避
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(1u32 << attempt.min(16))
+            .min(self.max_backoff);
+        backoff.mul_f64(0.5 + rand::random::<f64>() * 0.5)
+    }
+
+    pub fn parse(config: &mut Config, id: &str) -> Self {
+        AiRetryPolicy {
+            max_retries: config
+                .property_or_default(("enterprise.ai", id, "retry.attempts"), "2")
+                .unwrap_or(2),
+            initial_backoff: config
+                .property_or_default(("enterprise.ai", id, "retry.initial-backoff"), "500ms")
+                .unwrap_or_else(|| Duration::from_millis(500)),
+            max_backoff: config
+                .property_or_default(("enterprise.ai", id, "retry.max-backoff"), "30s")
+                .unwrap_or_else(|| Duration::from_secs(30)),
+        }
+    }
+}
+
+// 解析 Retry-After 头，支持秒数或 HTTP 日期
+pub fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        Some(Duration::from_secs(seconds))
+    } else {
+        let date = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+        Some(
+            (date.to_utc() - chrono::Utc::now())
+                .to_std()
+                .unwrap_or(Duration::ZERO),
+        )
+    }
+}
+
+impl AiRateLimiter {
+    // 先等待令牌再占用并发槽位，避免等待限流的请求占住空闲槽位；返回的许可在请求期间持有
+    pub async fn acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.acquire_token().await;
+        self.acquire_slot().await
+    }
+
+    // 等待空闲的并发槽位，请求期间须持有许可
+    async fn acquire_slot(&self) -> Option<OwnedSemaphorePermit> {
+        if let Some(concurrency) = &self.concurrency {
+            concurrency.clone().acquire_owned().await.ok()
+        } else {
+            None
+        }
+    }
+
+    // 等待令牌桶允许下一个请求
+    async fn acquire_token(&self) {
+        if let Some(bucket) = &self.bucket {
+            loop {
+                let wait = {
+                    let mut bucket = bucket.lock();
+                    bucket.refill();
+                    if bucket.tokens >= 1.0 {
+                        bucket.tokens -= 1.0;
+                        return;
+                    }
+                    Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.refill_per_sec)
+                };
+                tokio::time::sleep(wait).await;
+            }
+        }
+    }
+
+    pub fn parse(config: &mut Config, id: &str) -> Self {
+        AiRateLimiter {
+            concurrency: config
+                .property::<usize>(("enterprise.ai", id, "limits.concurrency"))
+                .filter(|&concurrency| concurrency > 0)
+                .map(|concurrency| Arc::new(Semaphore::new(concurrency))),
+            bucket: config
+                .property::<Rate>(("enterprise.ai", id, "limits.rate"))
+                .filter(|rate| rate.requests > 0 && !rate.period.is_zero())
+                .map(|rate| {
+                    Mutex::new(TokenBucket {
+                        capacity: rate.requests as f64,
+                        tokens: rate.requests as f64,
+                        refill_per_sec: rate.requests as f64 / rate.period.as_secs_f64(),
+                        last_refill: Instant::now(),
+                    })
+                }),
+        }
+    }
+}
+
+impl TokenBucket {
+    fn refill(&mut self) {
+        let now = Instant::now();
+        self.tokens = (self.tokens
+            + now.duration_since(self.last_refill).as_secs_f64() * self.refill_per_sec)
+            .min(self.capacity);
+        self.last_refill = now;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::{parse_retry_after, TokenBucket};
+
+    #[test]
+    fn retry_after() {
+        assert_eq!(parse_retry_after("120"), Some(Duration::from_secs(120)));
+        assert_eq!(parse_retry_after(" 0 "), Some(Duration::ZERO));
+        assert_eq!(
+            parse_retry_after("Sun, 06 Nov 1994 08:49:37 GMT"),
+            Some(Duration::ZERO)
+        );
+
+        let date = (chrono::Utc::now() + chrono::TimeDelta::seconds(60)).to_rfc2822();
+        let wait = parse_retry_after(&date).unwrap();
+        assert!(wait > Duration::from_secs(55) && wait <= Duration::from_secs(60));
+
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[test]
+    fn token_bucket_refill() {
+        let mut bucket = TokenBucket {
+            capacity: 10.0,
+            tokens: 0.0,
+            refill_per_sec: 5.0,
+            last_refill: Instant::now() - Duration::from_secs(1),
+        };
+        bucket.refill();
+        assert!((5.0..6.0).contains(&bucket.tokens));
+
+        // Never exceeds the capacity
+        bucket.last_refill = Instant::now() - Duration::from_secs(60);
+        bucket.refill();
+        assert_eq!(bucket.tokens, 10.0);
+    }
+}
@@ -0,0 +1,82 @@
+use std::sync::Arc;
+
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpListener,
+};
+
+use super::ChatCompletionRequest;
+
+// 启动本地 OpenAI 兼容的聊天补全端点，用 responder 的输出应答，返回端点 URL
+pub async fn spawn_mock_endpoint(
+    responder: impl Fn(&ChatCompletionRequest) -> String + Send + Sync + 'static,
+) -> std::io::Result<String> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let url = format!("http://{}/v1/chat/completions", listener.local_addr()?);
+    let responder = Arc::new(responder);
+
+    tokio::spawn(async move {
+        while let Ok((mut stream, _)) = listener.accept().await {
+            let responder = responder.clone();
+            tokio::spawn(async move {
+                let mut buf = Vec::new();
+                let mut chunk = [0u8; 4096];
+                let body = loop {
+                    match stream.read(&mut chunk).await {
+                        Ok(0) | Err(_) => return,
+                        Ok(n) => buf.extend_from_slice(&chunk[..n]),
+                    }
+                    if let Some(header_end) = buf.windows(4).position(|w| w == b"\r\n\r\n") {
+                        let content_length = std::str::from_utf8(&buf[..header_end])
+                            .unwrap_or_default()
+                            .lines()
+                            .find_map(|line| {
+                                let (name, value) = line.split_once(':')?;
+                                name.eq_ignore_ascii_case("content-length")
+                                    .then(|| value.trim().parse::<usize>().ok())
+                                    .flatten()
+                            })
+                            .unwrap_or_default();
+                        if buf.len() >= header_end + 4 + content_length {
+                            break buf[header_end + 4..header_end + 4 + content_length].to_vec();
+                        }
+                    }
+                };
+
+                let (status, response) =
+                    match serde_json::from_slice::<ChatCompletionRequest>(&body) {
+                        Ok(request) => (
+                            "200 OK",
+                            serde_json::json!({
+                                "created": 0,
+                                "object": "chat.completion",
+                                "id": "mock",
+                                "model": request.model,
+                                "choices": [{
+                                    "index": 0,
+                                    "finish_reason": "stop",
+                                    "message": {
+                                        "role": "assistant",
+                                        "content": responder(&request)
+                                    }
+                                }]
+                            })
+                            .to_string(),
+                        ),
+                        Err(err) => ("400 Bad Request", err.to_string()),
+                    };
+                let _ = stream
+                    .write_all(
+                        format!(
+                            "HTTP/1.1 {status}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{response}",
+                            response.len()
+                        )
+                        .as_bytes(),
+                    )
+                    .await;
+            });
+        }
+    });
+
+    Ok(url)
+}
@@ -0,0 +1,1660 @@
+pub mod assistant;
+pub mod audit;
+pub mod cache;
+pub mod deferred;
+pub mod embedding;
+pub mod eval;
+pub mod gate;
+pub mod health;
+pub mod limiter;
+pub mod mock;
+pub mod phishing;
+pub mod pool;
+pub mod redact;
+pub mod sieve;
+pub mod tenant;
+pub mod usage;
+
+use std::{
+    borrow::Cow,
+    collections::VecDeque,
+    future::Future,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+
+use audit::AiAuditLog;
+use cache::AiResponseCache;
+use futures::Stream;
+use health::AiApiHealth;
+use hyper::{
+    header::{CONTENT_TYPE, RETRY_AFTER},
+    HeaderMap, StatusCode,
+};
+use limiter::{parse_retry_after, AiRateLimiter, AiRetryPolicy};
+use redact::{AiRedactor, Redaction};
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use tenant::AiTenantGuard;
+use tokio::sync::OwnedSemaphorePermit;
+use trc::AddContext;
+use usage::{AiUsage, AiUsageTracker};
+use utils::config::Config;
+
+use crate::config::parse_http_headers;
+
+use super::{
+    LlmPromptTemplate, LlmPromptToken, LlmPromptVariable, LlmResponseFormat, LlmScoreRule,
+    SpamFilterLlmConfig,
+};
+
+#[derive(Clone, Debug)]
+pub struct AiApiConfig {
+    pub id: String,
+    pub api_type: ApiType,
+    pub url: String,
+    pub model: String,
+    pub timeout: Duration,
+    pub headers: HeaderMap,
+    pub tls_allow_invalid_certs: bool,
+    pub default_temperature: f64,
+    pub max_tokens: Option<u32>,
+    pub retry: AiRetryPolicy,
+    pub limiter: Arc<AiRateLimiter>,
+    pub client: reqwest::Client,
+    pub cache: Option<Arc<AiResponseCache>>,
+    pub usage: Arc<AiUsageTracker>,
+    pub redactor: Option<Arc<AiRedactor>>,
+    pub health: Arc<AiApiHealth>,
+    pub audit: Option<Arc<AiAuditLog>>,
+    pub tenants: Arc<AiTenantGuard>,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum ApiType {
+    ChatCompletion,
+    TextCompletion,
+    Anthropic,
+    Ollama,
+    Gemini,
+    Embedding,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatCompletionRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "std::ops::Not::not", default)]
+    pub stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub response_format: Option<serde_json::Value>,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub stream_options: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Message {
+    pub role: String,
+    pub content: String,
+}
+
+// 发送给 AI API 的对话：可选的系统指令和交替的用户、助手消息
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct AiPrompt {
+    pub system: Option<String>,
+    pub messages: Vec<Message>,
+    pub response_schema: Option<AiResponseSchema>,
+    pub tenant_id: Option<u32>,
+    pub account_id: Option<u32>,
+    pub feature: Option<&'static str>,
+}
+
+// 单个请求对端点配置行为的覆盖
+#[derive(Debug, Clone, Copy)]
+pub struct AiRequestOptions {
+    // 按端点的重试策略重试临时故障
+    pub retry: bool,
+    // 使用端点的响应缓存
+    pub cache: bool,
+    // 检查并计入端点的令牌预算
+    pub budget: bool,
+}
+
+// 要求模型输出遵循的 JSON 模式
+#[derive(Debug, Clone, PartialEq)]
+pub struct AiResponseSchema {
+    pub name: String,
+    pub schema: serde_json::Value,
+}
+
+// 从垃圾邮件过滤 LLM 响应中提取的分类
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LlmVerdict {
+    pub category: String,
+    pub confidence: Option<String>,
+    pub explanation: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct LlmJsonVerdict {
+    category: String,
+    #[serde(default)]
+    confidence: Option<String>,
+    #[serde(default)]
+    explanation: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatCompletionResponse {
+    pub created: i64,
+    pub object: String,
+    pub id: String,
+    pub model: String,
+    pub choices: Vec<ChatCompletionChoice>,
+    #[serde(default)]
+    pub usage: Option<CompletionUsage>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CompletionUsage {
+    #[serde(default)]
+    pub prompt_tokens: u64,
+    #[serde(default)]
+    pub completion_tokens: u64,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct ChatCompletionChoice {
+    pub index: i32,
+    pub finish_reason: String,
+    pub message: Message,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatCompletionChunk {
+    pub choices: Vec<ChatCompletionChunkChoice>,
+    #[serde(default)]
+    pub model: Option<String>,
+    #[serde(default)]
+    pub usage: Option<CompletionUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatCompletionChunkChoice {
+    pub index: i32,
+    #[serde(default)]
+    pub finish_reason: Option<String>,
+    pub delta: ChatCompletionDelta,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct ChatCompletionDelta {
+    #[serde(default)]
+    pub content: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct TextCompletionRequest {
+    pub model: String,
+    pub prompt: String,
+    pub temperature: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TextCompletionResponse {
+    pub created: i64,
+    pub object: String,
+    pub id: String,
+    pub model: String,
+    pub choices: Vec<TextCompletionChoice>,
+    #[serde(default)]
+    pub usage: Option<CompletionUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TextCompletionChoice {
+    pub index: i32,
+    pub finish_reason: String,
+    pub text: String,
+}
+
+struct ChatCompletionStream {
+    id: String,
+    url: String,
+    timeout: Duration,
+    response: reqwest::Response,
+    buf: Vec<u8>,
+    tokens: VecDeque<String>,
+    is_done: bool,
+    outcome: Option<StreamOutcome>,
+    _permit: Option<OwnedSemaphorePermit>,
+}
+
+// 流式响应的健康状态、令牌用量和审计记录，在流结束或被丢弃时写入
+struct StreamOutcome {
+    health: Arc<AiApiHealth>,
+    usage: Arc<AiUsageTracker>,
+    audit: Option<StreamAudit>,
+    tenant_id: Option<u32>,
+    start: Instant,
+    model: Option<String>,
+    tokens: Option<AiUsage>,
+    prompt_len: usize,
+    response_len: usize,
+}
+
+struct StreamAudit {
+    log: Arc<AiAuditLog>,
+    model: String,
+    prompt: AiPrompt,
+    response: String,
+}
+
+// 提供垃圾邮件过滤提示模板引用的邮件字段
+pub trait ResolvePromptVariable {
+    fn resolve_prompt_variable(&self, variable: LlmPromptVariable) -> Cow<'_, str>;
+}
+
+// 垃圾邮件过滤处理邮件时收集的提示模板变量值
+#[derive(Debug, Clone, Default)]
+pub struct LlmPromptContext<'x> {
+    pub subject: Cow<'x, str>,
+    pub from: Cow<'x, str>,
+    pub sender: Cow<'x, str>,
+    pub to: Cow<'x, str>,
+    pub headers: Cow<'x, str>,
+    pub body: Cow<'x, str>,
+    pub spf: Cow<'x, str>,
+    pub dkim: Cow<'x, str>,
+    pub dmarc: Cow<'x, str>,
+    pub tags: Cow<'x, str>,
+    pub score: f64,
+}
+
+impl ResolvePromptVariable for LlmPromptContext<'_> {
+    fn resolve_prompt_variable(&self, variable: LlmPromptVariable) -> Cow<'_, str> {
+        match variable {
+            LlmPromptVariable::Subject => self.subject.as_ref().into(),
+            LlmPromptVariable::From => self.from.as_ref().into(),
+            LlmPromptVariable::Sender => self.sender.as_ref().into(),
+            LlmPromptVariable::To => self.to.as_ref().into(),
+            LlmPromptVariable::Headers => self.headers.as_ref().into(),
+            LlmPromptVariable::Body => self.body.as_ref().into(),
+            LlmPromptVariable::Spf => self.spf.as_ref().into(),
+            LlmPromptVariable::Dkim => self.dkim.as_ref().into(),
+            LlmPromptVariable::Dmarc => self.dmarc.as_ref().into(),
+            LlmPromptVariable::Tags => self.tags.as_ref().into(),
+            LlmPromptVariable::Score => format!("{:.2}", self.score).into(),
+        }
+    }
+}
+
+struct AiResponse<T = String> {
+    output: T,
+    usage: Option<AiUsage>,
+    model: Option<String>,
+}
+
+struct ApiFailure {
+    message: String,
+    retryable: bool,
+    retry_after: Option<Duration>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct AnthropicRequest {
+    pub model: String,
+    pub max_tokens: u32,
+    pub messages: Vec<Message>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub system: Option<String>,
+    pub temperature: f64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AnthropicResponse {
+    pub id: String,
+    pub model: String,
+    pub content: Vec<AnthropicContent>,
+    #[serde(default)]
+    pub stop_reason: Option<String>,
+    #[serde(default)]
+    pub usage: Option<AnthropicUsage>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AnthropicUsage {
+    #[serde(default)]
+    pub input_tokens: u64,
+    #[serde(default)]
+    pub output_tokens: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct AnthropicContent {
+    #[serde(rename = "type")]
+    pub content_type: String,
+    #[serde(default)]
+    pub text: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OllamaRequest {
+    pub model: String,
+    pub messages: Vec<Message>,
+    pub stream: bool,
+    pub options: OllamaOptions,
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub format: Option<serde_json::Value>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct OllamaOptions {
+    pub temperature: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<u32>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct OllamaResponse {
+    pub model: String,
+    #[serde(default)]
+    pub created_at: String,
+    pub message: Message,
+    #[serde(default)]
+    pub done: bool,
+    #[serde(default)]
+    pub prompt_eval_count: Option<u64>,
+    #[serde(default)]
+    pub eval_count: Option<u64>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeminiRequest {
+    pub contents: Vec<GeminiContent>,
+    #[serde(rename = "systemInstruction", skip_serializing_if = "Option::is_none")]
+    pub system_instruction: Option<GeminiContent>,
+    #[serde(rename = "generationConfig")]
+    pub generation_config: GeminiGenerationConfig,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeminiContent {
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub role: Option<String>,
+    #[serde(default)]
+    pub parts: Vec<GeminiPart>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeminiPart {
+    #[serde(default)]
+    pub text: String,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct GeminiGenerationConfig {
+    pub temperature: f64,
+    #[serde(rename = "maxOutputTokens", skip_serializing_if = "Option::is_none")]
+    pub max_output_tokens: Option<u32>,
+    #[serde(rename = "responseMimeType", skip_serializing_if = "Option::is_none")]
+    pub response_mime_type: Option<String>,
+    #[serde(rename = "responseSchema", skip_serializing_if = "Option::is_none")]
+    pub response_schema: Option<serde_json::Value>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GeminiResponse {
+    #[serde(default)]
+    pub candidates: Vec<GeminiCandidate>,
+    #[serde(rename = "usageMetadata", default)]
+    pub usage_metadata: Option<GeminiUsage>,
+    #[serde(rename = "modelVersion", default)]
+    pub model_version: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GeminiUsage {
+    #[serde(rename = "promptTokenCount", default)]
+    pub prompt_token_count: u64,
+    #[serde(rename = "candidatesTokenCount", default)]
+    pub candidates_token_count: u64,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct GeminiCandidate {
+    pub content: GeminiContent,
+    #[serde(rename = "finishReason", default)]
+    pub finish_reason: Option<String>,
+}
+
+impl AiApiConfig {
+    pub async fn send_request(
+        &self,
+        prompt: impl Into<AiPrompt>,
+        temperature: Option<f64>,
+    ) -> trc::Result<String> {
+        self.send_request_with(prompt, temperature, AiRequestOptions::default())
+            .await
+    }
+
+    pub async fn send_request_with(
+        &self,
+        prompt: impl Into<AiPrompt>,
+        temperature: Option<f64>,
+        options: AiRequestOptions,
+    ) -> trc::Result<String> {
+        // Redact before computing the cache key so that cached responses never
+        // contain personal data from another message
+        let (prompt, redaction) = self.redact(prompt.into());
+        self.check_tenant(prompt.tenant_id)?;
+        let restore = |text: String| match &redaction {
+            Some(redaction) if !redaction.is_empty() => redaction.restore(&text),
+            _ => text,
+        };
+
+        let cache_key = self
+            .cache
+            .as_ref()
+            .filter(|_| options.cache)
+            .and_then(|cache| {
+                cache
+                    .key(
+                        &self.model,
+                        temperature.unwrap_or(self.default_temperature),
+                        &prompt,
+                    )
+                    .map(|key| (cache, key))
+            });
+        if let Some(response) = cache_key.and_then(|(cache, key)| cache.get(key)) {
+            return Ok(restore(response));
+        }
+
+        let response = self
+            .execute(
+                &prompt,
+                options,
+                || self.post_api(prompt.clone(), temperature),
+                |text| Cow::Borrowed(text.as_str()),
+            )
+            .await?;
+        if let Some((cache, key)) = cache_key {
+            cache.insert(key, response.clone());
+        }
+        Ok(restore(response))
+    }
+
+    // 在限流和预算下发送请求并重试临时故障，记录健康、用量和审计日志
+    async fn execute<T, Fut>(
+        &self,
+        prompt: &AiPrompt,
+        options: AiRequestOptions,
+        request: impl Fn() -> Fut,
+        summary: fn(&T) -> Cow<'_, str>,
+    ) -> trc::Result<T>
+    where
+        Fut: Future<Output = Result<AiResponse<T>, ApiFailure>>,
+    {
+        self.check_tenant(prompt.tenant_id)?;
+        if options.budget {
+            self.check_budget(prompt.tenant_id).await?;
+        }
+
+        let mut attempt = 0;
+
+        loop {
+            // The concurrency slot is only held while the request is in flight, not
+            // while waiting for a rate limit token or to retry
+            let permit = self.limiter.acquire().await;
+            let start = Instant::now();
+            let result = request().await;
+            drop(permit);
+            if let Some(audit) = &self.audit {
+                audit
+                    .record(
+                        &self.id,
+                        &self.model,
+                        prompt,
+                        result
+                            .as_ref()
+                            .map(|response| summary(&response.output))
+                            .as_deref()
+                            .map_err(|err| err.message.as_str()),
+                        start.elapsed(),
+                        result.as_ref().ok().and_then(|response| response.usage),
+                    )
+                    .await;
+            }
+            match result {
+                Ok(response) => {
+                    self.health
+                        .record_success(start.elapsed(), response.model.as_deref());
+                    if options.budget {
+                        self.usage.record(prompt.tenant_id, response.usage).await;
+                    }
+                    return Ok(response.output);
+                }
+                Err(err) if err.retryable && options.retry && attempt < self.retry.max_retries => {
+                    // Honour Retry-After, but never wait longer than the request timeout
+                    let wait = err
+                        .retry_after
+                        .map(|wait| wait.min(self.timeout))
+                        .unwrap_or_else(|| self.retry.backoff(attempt));
+                    attempt += 1;
+
+                    trc::event!(
+                        Ai(trc::AiEvent::ApiError),
+                        Id = self.id.clone(),
+                        Details = "Retrying AI API request",
+                        Reason = err.message,
+                        Total = attempt,
+                        Elapsed = wait,
+                    );
+
+                    tokio::time::sleep(wait).await;
+                }
+                Err(err) => {
+                    self.health.record_failure(&err.message);
+                    return Err(api_error(&self.id, err.message));
+                }
+            }
+        }
+    }
+
+    // 端点或租户的令牌预算耗尽时返回错误
+    async fn check_budget(&self, tenant_id: Option<u32>) -> trc::Result<()> {
+        if self
+            .usage
+            .is_over_budget(tenant_id)
+            .await
+            .caused_by(trc::location!())?
+        {
+            Err(trc::Error::new(trc::EventType::Ai(trc::AiEvent::ApiError))
+                .id(self.id.clone())
+                .details("Token budget exceeded"))
+        } else {
+            Ok(())
+        }
+    }
+
+    // 租户策略不允许使用此 API 时返回错误
+    fn check_tenant(&self, tenant_id: Option<u32>) -> trc::Result<()> {
+        if self.tenants.is_allowed(&self.id, tenant_id) {
+            Ok(())
+        } else {
+            Err(trc::Error::new(trc::EventType::Ai(trc::AiEvent::ApiError))
+                .id(self.id.clone())
+                .details("AI API not allowed by tenant policy"))
+        }
+    }
+
+    // 替换提示中的个人数据并统计每条规则的替换数
+    fn redact(&self, prompt: AiPrompt) -> (AiPrompt, Option<Redaction>) {
+        let Some(redactor) = &self.redactor else {
+            return (prompt, None);
+        };
+
+        let (prompt, redaction) = redactor.redact_prompt(&prompt);
+        for (kind, count) in redaction.counts() {
+            trc::event!(
+                Ai(trc::AiEvent::PromptRedacted),
+                Id = self.id.clone(),
+                Type = kind.clone(),
+                Total = *count,
+            );
+        }
+
+        (prompt, redactor.restore.then_some(redaction))
+    }
+
+    // 发送流式聊天补全请求，超时作用于两个数据块之间的等待
+    pub async fn send_request_stream(
+        &self,
+        prompt: impl Into<AiPrompt>,
+        temperature: Option<f64>,
+    ) -> trc::Result<impl Stream<Item = trc::Result<String>> + Send + 'static> {
+        // Placeholders are not restored in streamed tokens, as they may be split
+        // across chunks
+        let (prompt, _) = self.redact(prompt.into());
+        self.check_tenant(prompt.tenant_id)?;
+        self.check_budget(prompt.tenant_id).await?;
+        let tenant_id = prompt.tenant_id;
+        let prompt_len = prompt
+            .messages
+            .iter()
+            .map(|m| m.content.len())
+            .sum::<usize>()
+            + prompt.system.as_ref().map_or(0, |system| system.len());
+
+        let permit = self.limiter.acquire().await;
+        let start = Instant::now();
+        let response = match self.post_api_stream(prompt.clone(), temperature).await {
+            Ok(response) => response,
+            Err(err) => {
+                self.health.record_failure(&err);
+                if let Some(audit) = &self.audit {
+                    audit
+                        .record(
+                            &self.id,
+                            &self.model,
+                            &prompt,
+                            Err(&err),
+                            start.elapsed(),
+                            None,
+                        )
+                        .await;
+                }
+                return Err(api_error(&self.id, err));
+            }
+        };
+
+        Ok(futures::stream::unfold(
+            ChatCompletionStream {
+                id: self.id.clone(),
+                url: self.url.clone(),
+                timeout: self.timeout,
+                response,
+                buf: Vec::new(),
+                tokens: VecDeque::new(),
+                is_done: false,
+                outcome: Some(StreamOutcome {
+                    health: self.health.clone(),
+                    usage: self.usage.clone(),
+                    audit: self.audit.clone().map(|log| StreamAudit {
+                        log,
+                        model: self.model.clone(),
+                        prompt,
+                        response: String::new(),
+                    }),
+                    tenant_id,
+                    start,
+                    model: None,
+                    tokens: None,
+                    prompt_len,
+                    response_len: 0,
+                }),
+                _permit: permit,
+            },
+            |mut stream| async move { stream.next_token().await.map(|token| (token, stream)) },
+        ))
+    }
+
+    async fn post_api_stream(
+        &self,
+        prompt: impl Into<AiPrompt>,
+        temperature: Option<f64>,
+    ) -> Result<reqwest::Response, String> {
+        if !matches!(self.api_type, ApiType::ChatCompletion) {
+            return Err("Streaming is only supported by chat completion APIs".to_string());
+        }
+
+        let body = serde_json::to_string(&ChatCompletionRequest {
+            model: self.model.to_string(),
+            messages: prompt.into().chat_messages(),
+            temperature: temperature.unwrap_or(self.default_temperature),
+            stream: true,
+            response_format: None,
+            stream_options: Some(json!({ "include_usage": true })),
+        })
+        .map_err(|err| format!("Failed to serialize request: {}", err))?;
+
+        let response = self
+            .client
+            .post(&self.url)
+            .headers(self.headers.clone())
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| format!("API request to {} failed: {err}", self.url))?;
+
+        if response.status().is_success() {
+            Ok(response)
+        } else {
+            Err(format!(
+                "OpenAPI request to {} failed with code {}: {}",
+                self.url,
+                response.status().as_u16(),
+                response.status().canonical_reason().unwrap_or("Unknown")
+            ))
+        }
+    }
+
+    async fn post_api(
+        &self,
+        prompt: impl Into<AiPrompt>,
+        temperature: Option<f64>,
+    ) -> Result<AiResponse, ApiFailure> {
+        // Serialize body
+        let mut prompt = prompt.into();
+        if matches!(self.api_type, ApiType::TextCompletion | ApiType::Anthropic) {
+            // These APIs have no native structured output, request it in the instructions
+            prompt.inline_response_schema();
+        }
+        let temperature = temperature.unwrap_or(self.default_temperature);
+        let body = match self.api_type {
+            ApiType::ChatCompletion => serde_json::to_string(&ChatCompletionRequest {
+                model: self.model.to_string(),
+                messages: prompt.chat_messages(),
+                temperature,
+                stream: false,
+                stream_options: None,
+                response_format: prompt.response_schema.map(|schema| {
+                    json!({
+                        "type": "json_schema",
+                        "json_schema": {
+                            "name": schema.name,
+                            "schema": schema.schema,
+                            "strict": true
+                        }
+                    })
+                }),
+            })
+            .map_err(|err| format!("Failed to serialize request: {}", err))?,
+            ApiType::TextCompletion => serde_json::to_string(&TextCompletionRequest {
+                model: self.model.to_string(),
+                prompt: prompt.as_text(),
+                temperature,
+            })
+            .map_err(|err| format!("Failed to serialize request: {}", err))?,
+            ApiType::Anthropic => serde_json::to_string(&AnthropicRequest {
+                model: self.model.to_string(),
+                max_tokens: self.max_tokens.unwrap_or(1024),
+                messages: prompt.messages,
+                system: prompt.system,
+                temperature,
+            })
+            .map_err(|err| format!("Failed to serialize request: {}", err))?,
+            ApiType::Ollama => serde_json::to_string(&OllamaRequest {
+                model: self.model.to_string(),
+                messages: prompt.chat_messages(),
+                stream: false,
+                options: OllamaOptions {
+                    temperature,
+                    num_predict: self.max_tokens,
+                },
+                format: prompt.response_schema.map(|schema| schema.schema),
+            })
+            .map_err(|err| format!("Failed to serialize request: {}", err))?,
+            ApiType::Gemini => serde_json::to_string(&GeminiRequest {
+                generation_config: GeminiGenerationConfig {
+                    temperature,
+                    max_output_tokens: self.max_tokens,
+                    response_mime_type: prompt
+                        .response_schema
+                        .is_some()
+                        .then(|| "application/json".to_string()),
+                    response_schema: prompt.response_schema.map(|schema| {
+                        // Gemini rejects the "additionalProperties" keyword
+                        let mut schema = schema.schema;
+                        if let Some(schema) = schema.as_object_mut() {
+                            schema.remove("additionalProperties");
+                        }
+                        schema
+                    }),
+                },
+                contents: prompt
+                    .messages
+                    .into_iter()
+                    .map(|message| GeminiContent {
+                        role: if message.role == "assistant" {
+                            "model".to_string()
+                        } else {
+                            message.role
+                        }
+                        .into(),
+                        parts: vec![GeminiPart {
+                            text: message.content,
+                        }],
+                    })
+                    .collect(),
+                system_instruction: prompt.system.map(|text| GeminiContent {
+                    role: None,
+                    parts: vec![GeminiPart { text }],
+                }),
+            })
+            .map_err(|err| format!("Failed to serialize request: {}", err))?,
+            ApiType::Embedding => {
+                return Err(ApiFailure::from(
+                    "Embedding APIs do not support completions".to_string(),
+                ));
+            }
+        };
+
+        // Send request
+        let response = self
+            .client
+            .post(&self.url)
+            .timeout(self.timeout)
+            .headers(self.headers.clone())
+            .body(body)
+            .send()
+            .await
+            .map_err(|err| ApiFailure {
+                message: format!("API request to {} failed: {err}", self.url),
+                retryable: err.is_timeout() || err.is_connect(),
+                retry_after: None,
+            })?;
+
+        if response.status().is_success() {
+            let bytes = response.bytes().await.map_err(|err| {
+                format!("Failed to read response body from {}: {}", self.url, err)
+            })?;
+
+            let mut usage = None;
+            let mut model = None;
+            let result = match self.api_type {
+                ApiType::ChatCompletion => {
+                    let response = serde_json::from_slice::<ChatCompletionResponse>(&bytes)
+                        .map_err(|err| {
+                            format!(
+                                "Failed to chat completion parse response from {}: {}",
+                                self.url, err
+                            )
+                        })?;
+                    usage = response.usage.as_ref().map(AiUsage::from);
+                    model = Some(response.model);
+                    response
+                        .choices
+                        .into_iter()
+                        .next()
+                        .map(|choice| choice.message.content)
+                        .filter(|text| !text.is_empty())
+                        .ok_or_else(|| {
+                            format!(
+                                "Chat completion response from {} did not contain any choices: {}",
+                                self.url,
+                                std::str::from_utf8(&bytes).unwrap_or_default()
+                            )
+                        })
+                }
+                ApiType::TextCompletion => {
+                    let response = serde_json::from_slice::<TextCompletionResponse>(&bytes)
+                        .map_err(|err| {
+                            format!(
+                                "Failed to parse text completion response from {}: {}",
+                                self.url, err
+                            )
+                        })?;
+                    usage = response.usage.as_ref().map(AiUsage::from);
+                    model = Some(response.model);
+                    response
+                        .choices
+                        .into_iter()
+                        .next()
+                        .map(|choice| choice.text)
+                        .filter(|text| !text.is_empty())
+                        .ok_or_else(|| {
+                            format!(
+                                "Text completion response from {} did not contain any choices: {}",
+                                self.url,
+                                std::str::from_utf8(&bytes).unwrap_or_default()
+                            )
+                        })
+                }
+                ApiType::Anthropic => {
+                    let response =
+                        serde_json::from_slice::<AnthropicResponse>(&bytes).map_err(|err| {
+                            format!(
+                                "Failed to parse Anthropic response from {}: {}",
+                                self.url, err
+                            )
+                        })?;
+                    usage = response.usage.as_ref().map(|usage| AiUsage {
+                        input_tokens: usage.input_tokens,
+                        output_tokens: usage.output_tokens,
+                    });
+                    model = Some(response.model);
+                    response
+                        .content
+                        .into_iter()
+                        .filter(|content| content.content_type == "text")
+                        .filter_map(|content| content.text)
+                        .reduce(|mut text, part| {
+                            text.push_str(&part);
+                            text
+                        })
+                        .filter(|text| !text.is_empty())
+                        .ok_or_else(|| {
+                            format!(
+                                "Anthropic response from {} did not contain any text: {}",
+                                self.url,
+                                std::str::from_utf8(&bytes).unwrap_or_default()
+                            )
+                        })
+                }
+                ApiType::Ollama => {
+                    let response =
+                        serde_json::from_slice::<OllamaResponse>(&bytes).map_err(|err| {
+                            format!("Failed to parse Ollama response from {}: {}", self.url, err)
+                        })?;
+                    if response.prompt_eval_count.is_some() || response.eval_count.is_some() {
+                        usage = Some(AiUsage {
+                            input_tokens: response.prompt_eval_count.unwrap_or_default(),
+                            output_tokens: response.eval_count.unwrap_or_default(),
+                        });
+                    }
+                    model = Some(response.model);
+                    Some(response.message.content)
+                        .filter(|text| !text.is_empty())
+                        .ok_or_else(|| {
+                            format!(
+                                "Ollama response from {} did not contain any text: {}",
+                                self.url,
+                                std::str::from_utf8(&bytes).unwrap_or_default()
+                            )
+                        })
+                }
+                ApiType::Gemini => {
+                    let response =
+                        serde_json::from_slice::<GeminiResponse>(&bytes).map_err(|err| {
+                            format!("Failed to parse Gemini response from {}: {}", self.url, err)
+                        })?;
+                    usage = response.usage_metadata.as_ref().map(|usage| AiUsage {
+                        input_tokens: usage.prompt_token_count,
+                        output_tokens: usage.candidates_token_count,
+                    });
+                    model = response.model_version;
+                    response
+                        .candidates
+                        .into_iter()
+                        .next()
+                        .map(|candidate| {
+                            candidate
+                                .content
+                                .parts
+                                .into_iter()
+                                .map(|part| part.text)
+                                .collect::<String>()
+                        })
+                        .filter(|text| !text.is_empty())
+                        .ok_or_else(|| {
+                            format!(
+                                "Gemini response from {} did not contain any candidates: {}",
+                                self.url,
+                                std::str::from_utf8(&bytes).unwrap_or_default()
+                            )
+                        })
+                }
+                ApiType::Embedding => Err("Embedding APIs do not support completions".to_string()),
+            };
+            result
+                .map(|output| AiResponse {
+                    output,
+                    usage,
+                    model,
+                })
+                .map_err(ApiFailure::from)
+        } else {
+            Err(self.status_failure(&response))
+        }
+    }
+
+    // 描述失败的 HTTP 响应，限流和服务器错误可重试并遵循 Retry-After
+    fn status_failure(&self, response: &reqwest::Response) -> ApiFailure {
+        let status = response.status();
+        ApiFailure {
+            message: format!(
+                "OpenAPI request to {} failed with code {}: {}",
+                self.url,
+                status.as_u16(),
+                status.canonical_reason().unwrap_or("Unknown")
+            ),
+            retryable: matches!(
+                status,
+                StatusCode::TOO_MANY_REQUESTS
+                    | StatusCode::INTERNAL_SERVER_ERROR
+                    | StatusCode::BAD_GATEWAY
+                    | StatusCode::SERVICE_UNAVAILABLE
+                    | StatusCode::GATEWAY_TIMEOUT
+            ),
+            retry_after: matches!(
+                status,
+                StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+            )
+            .then(|| {
+                response
+                    .headers()
+                    .get(RETRY_AFTER)
+                    .and_then(|value| value.to_str().ok())
+                    .and_then(parse_retry_after)
+            })
+            .flatten(),
+        }
+    }
+
+    pub fn parse(config: &mut Config, id: &str) -> Option<Self> {
+        let url = config.value(("enterprise.ai", id, "url"))?.to_string();
+        let api_type = match config.value(("enterprise.ai", id, "type"))? {
+            "chat" => ApiType::ChatCompletion,
+            "text" => ApiType::TextCompletion,
+            "anthropic" => ApiType::Anthropic,
+            "ollama" => ApiType::Ollama,
+            "gemini" => ApiType::Gemini,
+            "embedding" => ApiType::Embedding,
+            _ => {
+                config.new_build_error(("enterprise.ai", id, "type"), "Invalid API type");
+                return None;
+            }
+        };
+
+        let mut headers = parse_http_headers(config, ("enterprise.ai", id));
+        headers.insert(CONTENT_TYPE, "application/json".parse().unwrap());
+        if matches!(api_type, ApiType::Anthropic) && !headers.contains_key("anthropic-version") {
+            headers.insert("anthropic-version", "2023-06-01".parse().unwrap());
+        }
+
+        let timeout = config
+            .property_or_default(("enterprise.ai", id, "timeout"), "2m")
+            .unwrap_or_else(|| Duration::from_secs(120));
+        let tls_allow_invalid_certs = config
+            .property_or_default(("enterprise.ai", id, "allow-invalid-certs"), "false")
+            .unwrap_or_default();
+
+        // Build a shared client so connections are reused across requests.
+        // No overall timeout is set here as streaming responses may take longer,
+        // non-streaming requests set it per request instead.
+        let mut builder = reqwest::Client::builder()
+            .connect_timeout(timeout)
+            .danger_accept_invalid_certs(tls_allow_invalid_certs)
+            .tcp_keepalive(
+                config
+                    .property_or_default::<Duration>(("enterprise.ai", id, "keep-alive"), "60s")
+                    .unwrap_or_else(|| Duration::from_secs(60)),
+            )
+            .pool_idle_timeout(
+                config
+                    .property_or_default::<Duration>(
+                        ("enterprise.ai", id, "pool.idle-timeout"),
+                        "90s",
+                    )
+                    .unwrap_or_else(|| Duration::from_secs(90)),
+            )
+            .pool_max_idle_per_host(
+                config
+                    .property_or_default(("enterprise.ai", id, "pool.max-idle"), "32")
+                    .unwrap_or(32),
+            );
+        if config
+            .property_or_default::<bool>(("enterprise.ai", id, "http2-only"), "false")
+            .unwrap_or_default()
+        {
+            builder = builder.http2_prior_knowledge();
+        }
+        if let Some(proxy) = config.value(("enterprise.ai", id, "proxy")) {
+            match reqwest::Proxy::all(proxy) {
+                Ok(proxy) => {
+                    builder = builder.proxy(proxy);
+                }
+                Err(err) => {
+                    config.new_build_error(
+                        ("enterprise.ai", id, "proxy"),
+                        format!("Invalid proxy URL: {err}"),
+                    );
+                    return None;
+                }
+            }
+        }
+        let client = match builder.build() {
+            Ok(client) => client,
+            Err(err) => {
+                config.new_build_error(
+                    ("enterprise.ai", id),
+                    format!("Failed to create HTTP client: {err}"),
+                );
+                return None;
+            }
+        };
+
+        Some(AiApiConfig {
+            id: id.to_string(),
+            api_type,
+            url,
+            headers,
+            model: config
+                .value_require(("enterprise.ai", id, "model"))?
+                .to_string(),
+            timeout,
+            tls_allow_invalid_certs,
+            default_temperature: config
+                .property_or_default(("enterprise.ai", id, "default-temperature"), "0.7")
+                .unwrap_or(0.7),
+            max_tokens: config.property(("enterprise.ai", id, "max-tokens")),
+            retry: AiRetryPolicy::parse(config, id),
+            limiter: AiRateLimiter::parse(config, id).into(),
+            client,
+            cache: AiResponseCache::parse(config, id).map(Arc::new),
+            usage: AiUsageTracker::parse(config, id).into(),
+            redactor: AiRedactor::parse(config, id).map(Arc::new),
+            health: AiApiHealth::parse(config, id).into(),
+            audit: None,
+            tenants: Default::default(),
+        })
+    }
+}
+
+impl AiPrompt {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_system(mut self, text: impl Into<String>) -> Self {
+        self.system = Some(text.into());
+        self
+    }
+
+    pub fn with_user(self, text: impl Into<String>) -> Self {
+        self.with_message("user", text)
+    }
+
+    pub fn with_assistant(self, text: impl Into<String>) -> Self {
+        self.with_message("assistant", text)
+    }
+
+    pub fn with_example(self, input: impl Into<String>, output: impl Into<String>) -> Self {
+        self.with_user(input).with_assistant(output)
+    }
+
+    pub fn with_tenant(mut self, tenant_id: Option<u32>) -> Self {
+        self.tenant_id = tenant_id;
+        self
+    }
+
+    pub fn with_account(mut self, account_id: Option<u32>) -> Self {
+        self.account_id = account_id;
+        self
+    }
+
+    // 发起请求的功能名称，用于审计日志和缓存判断
+    pub fn with_feature(mut self, feature: &'static str) -> Self {
+        self.feature = Some(feature);
+        self
+    }
+
+    pub fn with_response_schema(
+        mut self,
+        name: impl Into<String>,
+        schema: serde_json::Value,
+    ) -> Self {
+        self.response_schema = Some(AiResponseSchema {
+            name: name.into(),
+            schema,
+        });
+        self
+    }
+
+    fn inline_response_schema(&mut self) {
+        if let Some(schema) = self.response_schema.take() {
+            let instructions = format!(
+                "Respond only with a JSON object that conforms to this JSON schema: {}",
+                schema.schema
+            );
+            self.system = Some(match self.system.take() {
+                Some(system) => format!("{system}\n\n{instructions}"),
+                None => instructions,
+            });
+        }
+    }
+
+    fn with_message(mut self, role: &str, text: impl Into<String>) -> Self {
+        self.messages.push(Message {
+            role: role.to_string(),
+            content: text.into(),
+        });
+        self
+    }
+
+    fn chat_messages(&self) -> Vec<Message> {
+        self.system
+            .iter()
+            .map(|system| Message {
+                role: "system".to_string(),
+                content: system.clone(),
+            })
+            .chain(self.messages.iter().cloned())
+            .collect()
+    }
+
+    fn as_text(&self) -> String {
+        let mut text = String::new();
+        if let Some(system) = &self.system {
+            text.push_str(system);
+            text.push_str("\n\n");
+        }
+
+        if let [message] = self.messages.as_slice() {
+            text.push_str(&message.content);
+        } else {
+            for message in &self.messages {
+                text.push_str(if message.role == "assistant" {
+                    "Assistant: "
+                } else {
+                    "User: "
+                });
+                text.push_str(&message.content);
+                text.push_str("\n\n");
+            }
+            text.push_str("Assistant:");
+        }
+
+        text
+    }
+}
+
+impl Default for AiRequestOptions {
+    fn default() -> Self {
+        AiRequestOptions {
+            retry: true,
+            cache: true,
+            budget: true,
+        }
+    }
+}
+
+impl From<String> for AiPrompt {
+    fn from(text: String) -> Self {
+        AiPrompt::new().with_user(text)
+    }
+}
+
+impl From<&str> for AiPrompt {
+    fn from(text: &str) -> Self {
+        AiPrompt::new().with_user(text)
+    }
+}
+
+impl SpamFilterLlmConfig {
+    // 构建分类对话，指令放在系统消息中，邮件内容放在用户消息中
+    pub fn conversation(
+        &self,
+        message: &impl ResolvePromptVariable,
+        tenant_id: Option<u32>,
+    ) -> AiPrompt {
+        let prompt = self
+            .examples
+            .iter()
+            .fold(
+                AiPrompt::new()
+                    .with_feature("spam-filter")
+                    .with_tenant(tenant_id)
+                    .with_system(self.prompt.build(message)),
+                |prompt, (input, output)| prompt.with_example(input, output),
+            )
+            .with_user(self.message.build(message));
+
+        match self.response_format {
+            LlmResponseFormat::Json => {
+                prompt.with_response_schema("spam_classification", self.response_schema())
+            }
+            LlmResponseFormat::Text => prompt,
+        }
+    }
+
+    pub fn response_schema(&self) -> serde_json::Value {
+        let mut categories = self.categories.iter().collect::<Vec<_>>();
+        categories.sort_unstable();
+        let mut properties = serde_json::Map::new();
+        let mut required = vec!["category"];
+        properties.insert(
+            "category".to_string(),
+            json!({ "type": "string", "enum": categories }),
+        );
+        if !self.confidence.is_empty() {
+            let mut confidence = self.confidence.iter().collect::<Vec<_>>();
+            confidence.sort_unstable();
+            properties.insert(
+                "confidence".to_string(),
+                json!({ "type": "string", "enum": confidence }),
+            );
+            required.push("confidence");
+        }
+        properties.insert("explanation".to_string(), json!({ "type": "string" }));
+        required.push("explanation");
+
+        json!({
+            "type": "object",
+            "properties": properties,
+            "required": required,
+            "additionalProperties": false
+        })
+    }
+
+    pub fn parse_response(&self, response: &str) -> trc::Result<LlmVerdict> {
+        let verdict = match self.response_format {
+            LlmResponseFormat::Json => {
+                let json = extract_json_object(response)
+                    .ok_or("Response does not contain a JSON object")
+                    .map_err(|err| self.invalid_response(response, err))?;
+                serde_json::from_str::<LlmJsonVerdict>(json)
+                    .map(|verdict| LlmVerdict {
+                        category: verdict.category.trim().to_uppercase(),
+                        confidence: verdict
+                            .confidence
+                            .map(|confidence| confidence.trim().to_uppercase())
+                            .filter(|confidence| !confidence.is_empty()),
+                        explanation: verdict
+                            .explanation
+                            .map(|explanation| explanation.trim().to_string())
+                            .filter(|explanation| !explanation.is_empty()),
+                    })
+                    .map_err(|err| self.invalid_response(response, err))?
+            }
+            LlmResponseFormat::Text => {
+                let fields = response.split(self.separator).collect::<Vec<_>>();
+                let field = |index: Option<usize>| {
+                    index
+                        .and_then(|index| fields.get(index))
+                        .map(|field| field.trim())
+                        .filter(|field| !field.is_empty())
+                };
+                LlmVerdict {
+                    category: field(Some(self.index_category))
+                        .unwrap_or_default()
+                        .to_uppercase(),
+                    confidence: field(self.index_confidence).map(|field| field.to_uppercase()),
+                    explanation: field(self.index_explanation).map(|field| field.to_string()),
+                }
+            }
+        };
+
+        if !self.categories.contains(&verdict.category) {
+            Err(self.invalid_response(response, format!("Unknown category {:?}", verdict.category)))
+        } else if verdict
+            .confidence
+            .as_ref()
+            .is_some_and(|confidence| !self.confidence.contains(confidence))
+        {
+            Err(self.invalid_response(
+                response,
+                format!(
+                    "Unknown confidence {:?}",
+                    verdict.confidence.unwrap_or_default()
+                ),
+            ))
+        } else {
+            Ok(verdict)
+        }
+    }
+
+    // 返回分类结果的评分调整，优先匹配类别和置信度都符合的规则
+    pub fn score(&self, verdict: &LlmVerdict) -> Option<&LlmScoreRule> {
+        self.scores
+            .iter()
+            .find(|rule| {
+                rule.category == verdict.category
+                    && rule.confidence.is_some()
+                    && rule.confidence == verdict.confidence
+            })
+            .or_else(|| {
+                self.scores
+                    .iter()
+                    .find(|rule| rule.category == verdict.category && rule.confidence.is_none())
+            })
+    }
+
+    fn invalid_response(&self, response: &str, reason: impl std::fmt::Display) -> trc::Error {
+        trc::Error::new(trc::EventType::Ai(trc::AiEvent::InvalidResponse))
+            .id(self.model.id().to_string())
+            .details("Invalid LLM response")
+            .ctx(trc::Key::Contents, response.to_string())
+            .reason(reason)
+    }
+}
+
+impl LlmPromptTemplate {
+    pub fn build(&self, resolver: &impl ResolvePromptVariable) -> String {
+        let mut buf = String::new();
+        for token in &self.0 {
+            match token {
+                LlmPromptToken::Text(text) => buf.push_str(text),
+                LlmPromptToken::Variable { variable, limit } => {
+                    let value = resolver.resolve_prompt_variable(*variable);
+                    buf.push_str(truncate(&value, *limit));
+                    if value.len() > *limit {
+                        buf.push_str(" [truncated]");
+                    }
+                }
+            }
+        }
+        buf
+    }
+}
+
+impl LlmPromptVariable {
+    pub fn parse(name: &str) -> Option<Self> {
+        match name {
+            "subject" => Some(LlmPromptVariable::Subject),
+            "from" => Some(LlmPromptVariable::From),
+            "sender" => Some(LlmPromptVariable::Sender),
+            "to" => Some(LlmPromptVariable::To),
+            "headers" => Some(LlmPromptVariable::Headers),
+            "body" => Some(LlmPromptVariable::Body),
+            "spf" => Some(LlmPromptVariable::Spf),
+            "dkim" => Some(LlmPromptVariable::Dkim),
+            "dmarc" => Some(LlmPromptVariable::Dmarc),
+            "tags" => Some(LlmPromptVariable::Tags),
+            "score" => Some(LlmPromptVariable::Score),
+            _ => None,
+        }
+    }
+
+    // 未设置 spam-filter.llm.limits.<name> 时的默认截断长度（字节）
+    pub fn default_limit(&self) -> &'static str {
+        match self {
+            LlmPromptVariable::Body => "4096",
+            LlmPromptVariable::Headers => "2048",
+            LlmPromptVariable::To | LlmPromptVariable::Tags => "512",
+            LlmPromptVariable::Subject
+            | LlmPromptVariable::From
+            | LlmPromptVariable::Sender
+            | LlmPromptVariable::Spf
+            | LlmPromptVariable::Dkim
+            | LlmPromptVariable::Dmarc
+            | LlmPromptVariable::Score => "256",
+        }
+    }
+
+    // 服务器计算的值，只有这些值允许出现在系统指令中
+    pub fn is_trusted(&self) -> bool {
+        matches!(
+            self,
+            LlmPromptVariable::Spf
+                | LlmPromptVariable::Dkim
+                | LlmPromptVariable::Dmarc
+                | LlmPromptVariable::Tags
+                | LlmPromptVariable::Score
+        )
+    }
+}
+
+impl ChatCompletionStream {
+    async fn next_token(&mut self) -> Option<trc::Result<String>> {
+        loop {
+            if let Some(token) = self.tokens.pop_front() {
+                if let Some(outcome) = &mut self.outcome {
+                    outcome.response_len += token.len();
+                    if let Some(audit) = &mut outcome.audit {
+                        audit.response.push_str(&token);
+                    }
+                }
+                return Some(Ok(token));
+            } else if self.is_done {
+                self.finish(None);
+                return None;
+            }
+
+            let result = match tokio::time::timeout(self.timeout, self.response.chunk()).await {
+                Ok(Ok(Some(chunk))) => {
+                    self.buf.extend_from_slice(&chunk);
+                    self.parse_events()
+                }
+                Ok(Ok(None)) => {
+                    self.is_done = true;
+                    self.buf.push(b'\n');
+                    self.parse_events()
+                }
+                Ok(Err(err)) => Err(format!(
+                    "Failed to read response body from {}: {}",
+                    self.url, err
+                )),
+                Err(_) => Err(format!(
+                    "Timed out waiting for response chunk from {}",
+                    self.url
+                )),
+            };
+
+            if let Err(err) = result {
+                self.is_done = true;
+                self.tokens.clear();
+                self.finish(Some(&err));
+                return Some(Err(api_error(&self.id, err)));
+            }
+        }
+    }
+
+    // 记录端点健康状态、令牌用量和审计记录，提供方未报告用量时按文本长度估算
+    fn finish(&mut self, error: Option<&str>) {
+        let Some(outcome) = self.outcome.take() else {
+            return;
+        };
+        let latency = outcome.start.elapsed();
+        let id = self.id.clone();
+        let audit_error = match error {
+            Some(error) => Some(error.to_string()),
+            None if !self.is_done => Some("Stream dropped before completion".to_string()),
+            None => None,
+        };
+
+        match error {
+            None => outcome
+                .health
+                .record_success(outcome.start.elapsed(), outcome.model.as_deref()),
+            Some(error) => outcome.health.record_failure(error),
+        }
+
+        let tokens = outcome.tokens.unwrap_or(AiUsage {
+            input_tokens: outcome.prompt_len.div_ceil(4) as u64,
+            output_tokens: outcome.response_len.div_ceil(4) as u64,
+        });
+        if let Ok(handle) = tokio::runtime::Handle::try_current() {
+            handle.spawn(async move {
+                outcome.usage.record(outcome.tenant_id, Some(tokens)).await;
+                if let Some(audit) = outcome.audit {
+                    audit
+                        .log
+                        .record(
+                            &id,
+                            &audit.model,
+                            &audit.prompt,
+                            match &audit_error {
+                                Some(error) => Err(error.as_str()),
+                                None => Ok(audit.response.as_str()),
+                            },
+                            latency,
+                            Some(tokens),
+                        )
+                        .await;
+                }
+            });
+        }
+    }
+
+    fn parse_events(&mut self) -> Result<(), String> {
+        while let Some(pos) = self.buf.iter().position(|&ch| ch == b'\n') {
+            let line = self.buf.drain(..=pos).collect::<Vec<_>>();
+            let Some(data) = std::str::from_utf8(&line)
+                .map_err(|_| format!("Invalid UTF-8 in event stream from {}", self.url))?
+                .trim()
+                .strip_prefix("data:")
+                .map(|data| data.trim())
+            else {
+                continue;
+            };
+
+            if data == "[DONE]" {
+                self.is_done = true;
+                self.buf.clear();
+                break;
+            }
+
+            let chunk = serde_json::from_str::<ChatCompletionChunk>(data).map_err(|err| {
+                format!(
+                    "Failed to parse chat completion chunk from {}: {}",
+                    self.url, err
+                )
+            })?;
+            if let Some(outcome) = &mut self.outcome {
+                if chunk.model.is_some() {
+                    outcome.model = chunk.model;
+                }
+                if let Some(usage) = &chunk.usage {
+                    outcome.tokens = Some(AiUsage::from(usage));
+                }
+            }
+            for choice in chunk.choices {
+                if choice.index == 0 {
+                    if let Some(content) = choice.delta.content.filter(|text| !text.is_empty()) {
+                        self.tokens.push_back(content);
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ChatCompletionStream {
+    fn drop(&mut self) {
+        // 调用方提前停止读取时，提供方仍会对已生成的令牌计费，审计记录同样需要写入
+        self.finish(None);
+    }
+}
+
+impl From<&CompletionUsage> for AiUsage {
+    fn from(usage: &CompletionUsage) -> Self {
+        AiUsage {
+            input_tokens: usage.prompt_tokens,
+            output_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+impl From<String> for ApiFailure {
+    fn from(message: String) -> Self {
+        ApiFailure {
+            message,
+            retryable: false,
+            retry_after: None,
+        }
+    }
+}
+
+// 在字符边界处截断到 limit 字节以内
+fn truncate(value: &str, limit: usize) -> &str {
+    if value.len() > limit {
+        let mut end = limit;
+        while !value.is_char_boundary(end) {
+            end -= 1;
+        }
+        &value[..end]
+    } else {
+        value
+    }
+}
+
+// 返回响应中最外层的 JSON 对象
+fn extract_json_object(response: &str) -> Option<&str> {
+    response
+        .find('{')
+        .zip(response.rfind('}'))
+        .filter(|(start, end)| start < end)
+        .map(|(start, end)| &response[start..=end])
+}
+
+fn api_error(id: &str, err: String) -> trc::Error {
+    trc::Error::new(trc::EventType::Ai(trc::AiEvent::ApiError))
+        .id(id.to_string())
+        .details("OpenAPI request failed")
+        .reason(err)
+}
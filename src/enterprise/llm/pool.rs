@@ -0,0 +1,328 @@
+use std::{
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use ahash::AHashMap;
+use store::write::now;
+use utils::config::Config;
+
+use super::{health::AiCircuitState, AiApiConfig, AiPrompt, AiRequestOptions, ApiType};
+
+// 依次尝试直到成功的一组 AI 端点
+#[derive(Debug)]
+pub struct AiApiPool {
+    pub id: String,
+    pub strategy: AiPoolStrategy,
+    pub members: Vec<AiPoolMember>,
+    pub failure_threshold: u32,
+    pub cooldown: Duration,
+    next: AtomicUsize,
+}
+
+#[derive(Debug)]
+pub struct AiPoolMember {
+    pub api: Arc<AiApiConfig>,
+    pub weight: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AiPoolStrategy {
+    Priority,
+    RoundRobin,
+}
+
+// 单个 AI API 或 API 池
+#[derive(Debug, Clone)]
+pub enum AiEndpoint {
+    Api(Arc<AiApiConfig>),
+    Pool(Arc<AiApiPool>),
+}
+
+impl AiEndpoint {
+    pub async fn send_request(
+        &self,
+        prompt: impl Into<AiPrompt>,
+        temperature: Option<f64>,
+    ) -> trc::Result<String> {
+        self.send_request_with(prompt, temperature, AiRequestOptions::default())
+            .await
+    }
+
+    pub async fn send_request_with(
+        &self,
+        prompt: impl Into<AiPrompt>,
+        temperature: Option<f64>,
+        options: AiRequestOptions,
+    ) -> trc::Result<String> {
+        match self {
+            AiEndpoint::Api(api) => api.send_request_with(prompt, temperature, options).await,
+            AiEndpoint::Pool(pool) => pool.send_request_with(prompt, temperature, options).await,
+        }
+    }
+
+    pub fn id(&self) -> &str {
+        match self {
+            AiEndpoint::Api(api) => &api.id,
+            AiEndpoint::Pool(pool) => &pool.id,
+        }
+    }
+
+    // 端点可能使用的所有 API ID
+    pub fn api_ids(&self) -> Vec<String> {
+        match self {
+            AiEndpoint::Api(api) => vec![api.id.clone()],
+            AiEndpoint::Pool(pool) => pool
+                .members
+                .iter()
+                .map(|member| member.api.id.clone())
+                .collect(),
+        }
+    }
+}
+
+impl AiApiPool {
+    pub async fn send_request(
+        &self,
+        prompt: impl Into<AiPrompt>,
+        temperature: Option<f64>,
+    ) -> trc::Result<String> {
+        self.send_request_with(prompt, temperature, AiRequestOptions::default())
+            .await
+    }
+
+    // 依次尝试各成员，出错时切换到下一个，只有最后一个成员按其策略重试
+    pub async fn send_request_with(
+        &self,
+        prompt: impl Into<AiPrompt>,
+        temperature: Option<f64>,
+        options: AiRequestOptions,
+    ) -> trc::Result<String> {
+        let prompt = prompt.into();
+        let now = now();
+        let order = self.member_order();
+
+        let mut over_budget = Vec::with_capacity(self.members.len());
+        for member in &self.members {
+            over_budget
+                .push(options.budget && member.api.usage.is_over_budget(prompt.tenant_id).await?);
+        }
+
+        // When every circuit is open, try all members rather than failing outright
+        let is_available = |idx: usize| {
+            !over_budget[idx]
+                && self.members[idx].api.health.circuit_state(now) != AiCircuitState::Open
+        };
+        let has_available = order.iter().any(|&idx| is_available(idx));
+        let mut last_err = None;
+
+        for (pos, &idx) in order.iter().enumerate() {
+            let member = &self.members[idx];
+            if over_budget[idx] {
+                continue;
+            }
+            let is_probe = match member.api.health.circuit_state(now) {
+                AiCircuitState::Closed => false,
+                AiCircuitState::Open if has_available => continue,
+                AiCircuitState::Open => false,
+                AiCircuitState::HalfOpen if member.api.health.try_probe(now, self.cooldown) => true,
+                AiCircuitState::HalfOpen if has_available => continue,
+                AiCircuitState::HalfOpen => false,
+            };
+            let is_last = !has_available || !order[pos + 1..].iter().any(|&idx| is_available(idx));
+
+            match member
+                .api
+                .send_request_with(
+                    prompt.clone(),
+                    temperature,
+                    AiRequestOptions {
+                        retry: options.retry && is_last,
+                        ..options
+                    },
+                )
+                .await
+            {
+                // The endpoint records the outcome in its health, which also closes the circuit
+                Ok(response) => return Ok(response),
+                Err(err) => {
+                    if member
+                        .api
+                        .health
+                        .trip(self.failure_threshold, self.cooldown)
+                    {
+                        trc::event!(
+                            Ai(trc::AiEvent::ApiError),
+                            Id = member.api.id.clone(),
+                            Details = "Circuit opened for AI endpoint",
+                            Reason = if is_probe {
+                                "Half-open probe failed".to_string()
+                            } else {
+                                format!("{} consecutive failures", self.failure_threshold)
+                            },
+                        );
+                    }
+                    last_err = Some(err);
+                }
+            }
+        }
+
+        Err(last_err.unwrap_or_else(|| {
+            trc::Error::new(trc::EventType::Ai(trc::AiEvent::ApiError))
+                .id(self.id.clone())
+                .details("AI pool has no endpoints")
+        }))
+    }
+
+    fn member_order(&self) -> Vec<usize> {
+        let total = self.members.len();
+        match self.strategy {
+            AiPoolStrategy::Priority => (0..total).collect(),
+            AiPoolStrategy::RoundRobin => {
+                let total_weight = self
+                    .members
+                    .iter()
+                    .map(|member| member.weight as usize)
+                    .sum::<usize>()
+                    .max(1);
+                let mut slot = self.next.fetch_add(1, Ordering::Relaxed) % total_weight;
+                let first = self
+                    .members
+                    .iter()
+                    .position(|member| {
+                        if slot < member.weight as usize {
+                            true
+                        } else {
+                            slot -= member.weight as usize;
+                            false
+                        }
+                    })
+                    .unwrap_or_default();
+
+                (0..total).map(|idx| (first + idx) % total).collect()
+            }
+        }
+    }
+
+    pub fn parse(
+        config: &mut Config,
+        id: &str,
+        apis: &AHashMap<String, Arc<AiApiConfig>>,
+    ) -> Option<Self> {
+        let strategy = match config
+            .value(("enterprise.ai-pool", id, "strategy"))
+            .unwrap_or("priority")
+        {
+            "priority" => AiPoolStrategy::Priority,
+            "round-robin" => AiPoolStrategy::RoundRobin,
+            _ => {
+                config.new_build_error(
+                    ("enterprise.ai-pool", id, "strategy"),
+                    "Invalid pool strategy, expected \"priority\" or \"round-robin\"",
+                );
+                return None;
+            }
+        };
+
+        let mut members = Vec::new();
+        for member_id in config
+            .values(("enterprise.ai-pool", id, "members"))
+            .map(|(_, v)| v.trim().to_string())
+            .collect::<Vec<_>>()
+        {
+            match apis.get(&member_id) {
+                Some(api) if matches!(api.api_type, ApiType::Embedding) => {
+                    config.new_build_error(
+                        ("enterprise.ai-pool", id, "members"),
+                        format!("AI API {member_id:?} is an embedding API"),
+                    );
+                }
+                Some(api) => members.push(AiPoolMember {
+                    api: api.clone(),
+                    weight: config
+                        .property_or_default(
+                            format!("enterprise.ai-pool.{id}.weight.{member_id}"),
+                            "1",
+                        )
+                        .unwrap_or(1),
+                }),
+                None => {
+                    config.new_build_error(
+                        ("enterprise.ai-pool", id, "members"),
+                        format!("AI API {member_id:?} not found"),
+                    );
+                }
+            }
+        }
+
+        if members.is_empty() {
+            config.new_build_error(("enterprise.ai-pool", id, "members"), "No members defined");
+            return None;
+        }
+
+        Some(AiApiPool {
+            id: id.to_string(),
+            strategy,
+            members,
+            failure_threshold: config
+                .property_or_default(("enterprise.ai-pool", id, "failure-threshold"), "3")
+                .unwrap_or(3),
+            cooldown: config
+                .property_or_default(("enterprise.ai-pool", id, "cooldown"), "30s")
+                .unwrap_or_else(|| Duration::from_secs(30)),
+            next: AtomicUsize::new(0),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{sync::Arc, time::Duration};
+
+    use super::{AiApiPool, AiPoolMember, AiPoolStrategy};
+    use crate::enterprise::llm::AiApiConfig;
+
+    #[test]
+    fn member_order() {
+        let api = Arc::new(
+            AiApiConfig::parse(
+                &mut utils::config::Config::new(concat!(
+                    "[enterprise.ai.test]\n",
+                    "url = \"https://localhost/v1/chat/completions\"\n",
+                    "type = \"chat\"\n",
+                    "model = \"test\"\n",
+                ))
+                .unwrap(),
+                "test",
+            )
+            .unwrap(),
+        );
+        let pool = |strategy, weights: &[u32]| AiApiPool {
+            id: "pool".to_string(),
+            strategy,
+            members: weights
+                .iter()
+                .map(|&weight| AiPoolMember {
+                    api: api.clone(),
+                    weight,
+                })
+                .collect(),
+            failure_threshold: 3,
+            cooldown: Duration::from_secs(30),
+            next: Default::default(),
+        };
+
+        let priority = pool(AiPoolStrategy::Priority, &[1, 1, 1]);
+        assert_eq!(priority.member_order(), vec![0, 1, 2]);
+        assert_eq!(priority.member_order(), vec![0, 1, 2]);
+
+        let round_robin = pool(AiPoolStrategy::RoundRobin, &[2, 1]);
+        assert_eq!(round_robin.member_order(), vec![0, 1]);
+        assert_eq!(round_robin.member_order(), vec![0, 1]);
+        assert_eq!(round_robin.member_order(), vec![1, 0]);
+        assert_eq!(round_robin.member_order(), vec![0, 1]);
+    }
+}
@@ -0,0 +1,442 @@
+use regex::Regex;
+use utils::config::Config;
+
+use super::AiPrompt;
+
+// 发送前将提示中的个人数据替换为编号占位符，并在响应中还原
+#[derive(Debug)]
+pub struct AiRedactor {
+    pub rules: Vec<AiRedactRule>,
+    pub restore: bool,
+}
+
+#[derive(Debug)]
+pub struct AiRedactRule {
+    pub name: String,
+    pub regex: Regex,
+    validate: Option<Validator>,
+}
+
+// 校验正则匹配并返回最长有效前缀的长度
+type Validator = fn(&str) -> Option<usize>;
+
+// 脱敏单个提示时生成的占位符
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Redaction {
+    entries: Vec<(String, String)>,
+    counts: Vec<(String, u64)>,
+}
+
+const BUILTIN_DETECTORS: &[&str] = &["email", "iban", "card", "phone"];
+
+impl AiRedactor {
+    pub fn redact_prompt(&self, prompt: &AiPrompt) -> (AiPrompt, Redaction) {
+        let mut redaction = Redaction::default();
+        let mut prompt = prompt.clone();
+        if let Some(system) = &mut prompt.system {
+            *system = self.redact(system, &mut redaction);
+        }
+        for message in &mut prompt.messages {
+            message.content = self.redact(&message.content, &mut redaction);
+        }
+        (prompt, redaction)
+    }
+
+    pub fn redact(&self, text: &str, redaction: &mut Redaction) -> String {
+        let mut text = text.to_string();
+        for rule in &self.rules {
+            let mut result = String::with_capacity(text.len());
+            let mut last = 0;
+            let mut pos = 0;
+            let mut count = 0;
+
+            while let Some(m) = rule.regex.find_at(&text, pos) {
+                let len = match rule.validate {
+                    Some(validate) => validate(m.as_str()),
+                    None => Some(m.len()),
+                };
+                match len.filter(|&len| len > 0) {
+                    Some(len) => {
+                        let end = m.start() + len;
+                        result.push_str(&text[last..m.start()]);
+                        result.push_str(&redaction.placeholder(&rule.name, &text[m.start()..end]));
+                        last = end;
+                        pos = end;
+                        count += 1;
+                    }
+                    None if m.is_empty() => {
+                        // Skip a character so that empty matches cannot loop forever
+                        pos = m.end() + text[m.end()..].chars().next().map_or(1, char::len_utf8);
+                    }
+                    None => pos = m.end(),
+                }
+                if pos >= text.len() {
+                    break;
+                }
+            }
+
+            if count > 0 {
+                result.push_str(&text[last..]);
+                text = result;
+                redaction.count(&rule.name, count);
+            }
+        }
+        text
+    }
+
+    // 使用所有内置检测器且不还原的脱敏器
+    pub fn builtin() -> Self {
+        AiRedactor {
+            rules: BUILTIN_DETECTORS
+                .iter()
+                .filter_map(|name| AiRedactRule::builtin(name))
+                .collect(),
+            restore: false,
+        }
+    }
+
+    pub fn parse(config: &mut Config, id: &str) -> Option<Self> {
+        if !config
+            .property_or_default::<bool>(("enterprise.ai", id, "redact.enable"), "false")
+            .unwrap_or_default()
+        {
+            return None;
+        }
+
+        let mut detectors = config
+            .values(("enterprise.ai", id, "redact.detectors"))
+            .map(|(_, v)| v.trim().to_lowercase())
+            .collect::<Vec<_>>();
+        if detectors.is_empty() {
+            detectors = BUILTIN_DETECTORS.iter().map(|d| d.to_string()).collect();
+        }
+
+        let mut rules = Vec::new();
+        for detector in detectors {
+            if detector == "none" {
+                continue;
+            } else if let Some(rule) = AiRedactRule::builtin(&detector) {
+                rules.push(rule);
+            } else {
+                config.new_build_error(
+                    ("enterprise.ai", id, "redact.detectors"),
+                    format!("Unknown redaction detector {detector:?}"),
+                );
+            }
+        }
+
+        for name in config
+            .sub_keys(("enterprise.ai", id, "redact.rule"), "")
+            .map(|s| s.to_string())
+            .collect::<Vec<_>>()
+        {
+            let key = format!("enterprise.ai.{id}.redact.rule.{name}");
+            let Some(pattern) = config.value(key.as_str()).map(|s| s.to_string()) else {
+                continue;
+            };
+            match Regex::new(&pattern) {
+                Ok(regex) => rules.push(AiRedactRule {
+                    name: name.to_lowercase(),
+                    regex,
+                    validate: None,
+                }),
+                Err(err) => {
+                    config.new_build_error(
+                        key.as_str(),
+                        format!("Invalid regular expression: {err}"),
+                    );
+                }
+            }
+        }
+
+        Some(AiRedactor {
+            rules,
+            restore: config
+                .property_or_default(("enterprise.ai", id, "redact.restore"), "true")
+                .unwrap_or(true),
+        })
+    }
+}
+
+impl AiRedactRule {
+    fn builtin(name: &str) -> Option<Self> {
+        let (pattern, validate): (&str, Option<Validator>) = match name {
+            "email" => (
+                r"(?i)\b[a-z0-9._%+-]+@[a-z0-9-]+(?:\.[a-z0-9-]+)*\.[a-z]{2,}\b",
+                None,
+            ),
+            "iban" => (r"\b[A-Z]{2}\d{2}(?: ?[A-Z0-9]){11,30}\b", Some(iban_length)),
+            "card" => (r"\b(?:\d[ -]?){12,18}\d\b", Some(card_length)),
+            "phone" => (r"(?:\+|\b)\d[\d ().-]{7,}\d\b", Some(phone_length)),
+            _ => return None,
+        };
+
+        Some(AiRedactRule {
+            name: name.to_string(),
+            regex: Regex::new(pattern).unwrap(),
+            validate,
+        })
+    }
+}
+
+impl Redaction {
+    // 返回值的占位符，相同的值复用同一个占位符
+    fn placeholder(&mut self, name: &str, value: &str) -> String {
+        if let Some((placeholder, _)) = self.entries.iter().find(|(_, v)| v == value) {
+            return placeholder.clone();
+        }
+        let prefix = format!("[{}_", name.to_uppercase());
+        let number = self
+            .entries
+            .iter()
+            .filter(|(placeholder, _)| placeholder.starts_with(&prefix))
+            .count()
+            + 1;
+        let placeholder = format!("{prefix}{number}]");
+        self.entries.push((placeholder.clone(), value.to_string()));
+        placeholder
+    }
+
+    // 将响应中的占位符还原为原始值
+    pub fn restore(&self, text: &str) -> String {
+        self.entries
+            .iter()
+            .fold(text.to_string(), |text, (placeholder, value)| {
+                text.replace(placeholder.as_str(), value)
+            })
+    }
+
+    fn count(&mut self, name: &str, count: u64) {
+        match self.counts.iter_mut().find(|(kind, _)| kind == name) {
+            Some((_, total)) => *total += count,
+            None => self.counts.push((name.to_string(), count)),
+        }
+    }
+
+    // 每条规则的替换数
+    pub fn counts(&self) -> &[(String, u64)] {
+        &self.counts
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+fn is_valid_card(value: &str) -> bool {
+    let digits = value
+        .chars()
+        .filter_map(|ch| ch.to_digit(10))
+        .collect::<Vec<_>>();
+    (13..=19).contains(&digits.len())
+        && digits
+            .iter()
+            .rev()
+            .enumerate()
+            .map(|(i, &digit)| {
+                if i % 2 == 1 {
+                    let double = digit * 2;
+                    if double > 9 {
+                        double - 9
+                    } else {
+                        double
+                    }
+                } else {
+                    digit
+                }
+            })
+            .sum::<u32>()
+            % 10
+            == 0
+}
+
+fn is_valid_iban(value: &str) -> bool {
+    let iban = value.replace(' ', "");
+    if !(15..=34).contains(&iban.len()) {
+        return false;
+    }
+    let (head, tail) = iban.split_at(4);
+    let mut remainder = 0u32;
+    for ch in tail.chars().chain(head.chars()) {
+        let Some(digit) = ch.to_digit(36) else {
+            return false;
+        };
+        remainder = if digit >= 10 {
+            (remainder * 100 + digit) % 97
+        } else {
+            (remainder * 10 + digit) % 97
+        };
+    }
+    remainder == 1
+}
+
+// 由完整数字组构成的最长有效卡号前缀
+fn card_length(value: &str) -> Option<usize> {
+    let mut digits = 0;
+    let mut ends = Vec::new();
+    for (pos, ch) in value.char_indices() {
+        if ch.is_ascii_digit() {
+            digits += 1;
+            let at_group_end = !value[pos + 1..].starts_with(|ch: char| ch.is_ascii_digit());
+            if (13..=19).contains(&digits) && at_group_end {
+                ends.push(pos + 1);
+            }
+        }
+    }
+    ends.into_iter()
+        .rev()
+        .find(|&end| is_valid_card(&value[..end]))
+}
+
+// 符合国家 IBAN 长度并通过校验的最长前缀
+fn iban_length(value: &str) -> Option<usize> {
+    let expected = value.get(..2).and_then(iban_country_length);
+    let mut chars = 0;
+    let mut ends = Vec::new();
+    for (pos, ch) in value.char_indices() {
+        if ch.is_ascii_alphanumeric() {
+            chars += 1;
+            if expected.map_or((15..=34).contains(&chars), |expected| expected == chars) {
+                ends.push(pos + 1);
+            }
+        }
+    }
+    ends.into_iter()
+        .rev()
+        .find(|&end| is_valid_iban(&value[..end]))
+}
+
+fn iban_country_length(country: &str) -> Option<usize> {
+    match country {
+        "NO" => Some(15),
+        "BE" => Some(16),
+        "DK" | "FI" | "FO" | "GL" | "NL" => Some(18),
+        "MK" | "SI" => Some(19),
+        "AT" | "BA" | "EE" | "KZ" | "LT" | "LU" | "XK" => Some(20),
+        "CH" | "CR" | "HR" | "LI" | "LV" => Some(21),
+        "BG" | "BH" | "DE" | "GB" | "GE" | "IE" | "ME" | "RS" => Some(22),
+        "AE" | "GI" | "IL" | "TL" => Some(23),
+        "AD" | "CZ" | "ES" | "MD" | "PK" | "RO" | "SA" | "SE" | "SK" | "TN" | "VG" => Some(24),
+        "PT" => Some(25),
+        "IS" | "TR" => Some(26),
+        "FR" | "GR" | "IT" | "MC" | "MR" | "SM" => Some(27),
+        "AL" | "AZ" | "CY" | "DO" | "GT" | "HU" | "LB" | "PL" => Some(28),
+        "BR" | "PS" | "QA" | "UA" => Some(29),
+        "JO" | "KW" | "MU" => Some(30),
+        "MT" => Some(31),
+        _ => None,
+    }
+}
+
+fn phone_length(value: &str) -> Option<usize> {
+    ((9..=15).contains(&value.chars().filter(|ch| ch.is_ascii_digit()).count())
+        && !contains_date(value))
+    .then_some(value.len())
+}
+
+// 值中是否包含日期，避免电话号码规则误匹配
+fn contains_date(value: &str) -> bool {
+    value.split([' ', '(', ')']).any(|token| {
+        ['-', '.', '/'].into_iter().any(|separator| {
+            let parts = token.split(separator).collect::<Vec<_>>();
+            if parts.len() != 3
+                || parts
+                    .iter()
+                    .any(|part| part.is_empty() || !part.bytes().all(|b| b.is_ascii_digit()))
+            {
+                return false;
+            }
+            let numbers = parts
+                .iter()
+                .map(|part| part.parse::<u32>().unwrap_or(u32::MAX))
+                .collect::<Vec<_>>();
+            let is_day = |n: u32| (1..=31).contains(&n);
+            let is_month = |n: u32| (1..=12).contains(&n);
+            if parts[0].len() == 4 {
+                is_month(numbers[1]) && is_day(numbers[2])
+            } else {
+                matches!(parts[2].len(), 2 | 4)
+                    && parts[0].len() <= 2
+                    && parts[1].len() <= 2
+                    && ((is_day(numbers[0]) && is_month(numbers[1]))
+                        || (is_month(numbers[0]) && is_day(numbers[1])))
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_card, is_valid_iban, AiRedactor, Redaction};
+
+    fn redact(text: &str) -> (String, Redaction) {
+        let mut redaction = Redaction::default();
+        let text = AiRedactor::builtin().redact(text, &mut redaction);
+        (text, redaction)
+    }
+
+    #[test]
+    fn checksums() {
+        assert!(is_valid_card("4111 1111 1111 1111"));
+        assert!(is_valid_card("5500-0000-0000-0004"));
+        assert!(!is_valid_card("4111 1111 1111 1112"));
+        assert!(!is_valid_card("4111"));
+
+        assert!(is_valid_iban("DE89 3704 0044 0532 0130 00"));
+        assert!(is_valid_iban("GB82WEST12345698765432"));
+        assert!(!is_valid_iban("DE89 3704 0044 0532 0130 01"));
+        assert!(!is_valid_iban("DE89"));
+    }
+
+    #[test]
+    fn redact_values() {
+        for (text, expected) in [
+            (
+                "Write to john.doe@example.org today",
+                "Write to [EMAIL_1] today",
+            ),
+            (
+                "Pay to DE89 3704 0044 0532 0130 00 BIC COBADEFFXXX",
+                "Pay to [IBAN_1] BIC COBADEFFXXX",
+            ),
+            ("IBAN GB82WEST12345698765432.", "IBAN [IBAN_1]."),
+            (
+                "Card 4111 1111 1111 1111 2024 expires",
+                "Card [CARD_1] 2024 expires",
+            ),
+            ("Card 4111-1111-1111-1111", "Card [CARD_1]"),
+            ("Call +1 (555) 123-4567 now", "Call [PHONE_1] now"),
+            ("Call 0049 30 1234567", "Call [PHONE_1]"),
+            (
+                "Sent on 2024-01-15 10:30:45 UTC",
+                "Sent on 2024-01-15 10:30:45 UTC",
+            ),
+            ("Due 15.01.2024 1200 EUR", "Due 15.01.2024 1200 EUR"),
+            ("Order 4111 1111 1111 1112", "Order 4111 1111 1111 1112"),
+            (
+                "a@example.org wrote to a@example.org and b@example.org",
+                "[EMAIL_1] wrote to [EMAIL_1] and [EMAIL_2]",
+            ),
+        ] {
+            assert_eq!(redact(text).0, expected, "{text}");
+        }
+    }
+
+    #[test]
+    fn restore_and_counts() {
+        let (text, redaction) = redact("Mail a@example.org, card 4111 1111 1111 1111");
+        assert_eq!(text, "Mail [EMAIL_1], card [CARD_1]");
+        assert_eq!(
+            redaction.restore("Reply to [EMAIL_1] about [CARD_1]"),
+            "Reply to a@example.org about 4111 1111 1111 1111"
+        );
+        assert_eq!(
+            redaction.counts(),
+            &[("email".to_string(), 1), ("card".to_string(), 1)]
+        );
+    }
+}
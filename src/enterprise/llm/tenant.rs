@@ -0,0 +1,181 @@
+use std::sync::OnceLock;
+
+use ahash::AHashMap;
+use directory::{backend::internal::PrincipalField, QueryBy, Type};
+use store::Store;
+use trc::AddContext;
+
+use crate::Server;
+
+use super::pool::AiEndpoint;
+
+// 租户邮件发送到 AI 端点的策略
+#[derive(Debug, Clone)]
+pub enum AiTenantPolicy {
+    Disable,
+    Override(AiEndpoint),
+}
+
+// 请求使用的端点及计入的租户
+#[derive(Debug, Clone)]
+pub struct AiTenantEndpoint {
+    pub endpoint: AiEndpoint,
+    pub tenant_id: Option<u32>,
+}
+
+// 按租户 ID 检查允许使用的 AI API，直接调用端点时也会执行租户策略
+#[derive(Debug, Default)]
+pub struct AiTenantGuard {
+    // 租户 ID 到允许的 API ID，None 表示已禁用
+    tenants: OnceLock<AHashMap<u32, Option<Vec<String>>>>,
+}
+
+impl AiTenantGuard {
+    // 配置加载时将租户名称解析为 ID
+    pub async fn init(
+        &self,
+        data: &Store,
+        policies: &AHashMap<String, AiTenantPolicy>,
+    ) -> trc::Result<()> {
+        let mut tenants = AHashMap::with_capacity(policies.len());
+        for (name, policy) in policies {
+            let Some(tenant) = data
+                .query(QueryBy::Name(name), false)
+                .await
+                .caused_by(trc::location!())?
+                .filter(|p| p.typ() == Type::Tenant)
+            else {
+                continue;
+            };
+            tenants.insert(
+                tenant.id(),
+                match policy {
+                    AiTenantPolicy::Disable => None,
+                    AiTenantPolicy::Override(endpoint) => Some(endpoint.api_ids()),
+                },
+            );
+        }
+        let _ = self.tenants.set(tenants);
+        Ok(())
+    }
+
+    pub fn is_allowed(&self, api_id: &str, tenant_id: Option<u32>) -> bool {
+        match (self.tenants.get(), tenant_id) {
+            (Some(tenants), Some(tenant_id)) => tenants.get(&tenant_id).map_or(true, |allowed| {
+                allowed
+                    .as_ref()
+                    .is_some_and(|ids| ids.iter().any(|id| id == api_id))
+            }),
+            _ => true,
+        }
+    }
+}
+
+impl Server {
+    // 按拥有域名的租户策略解析端点，租户禁用 AI 时返回 None
+    pub async fn ai_endpoint_for_domain(
+        &self,
+        default: &AiEndpoint,
+        domain: &str,
+    ) -> trc::Result<Option<AiTenantEndpoint>> {
+        self.ai_endpoint_for_domains(default, [domain]).await
+    }
+
+    // 为发往多个域名的邮件解析端点，所有租户都须允许且端点一致，否则返回 None
+    pub async fn ai_endpoint_for_domains<'x>(
+        &self,
+        default: &AiEndpoint,
+        domains: impl IntoIterator<Item = &'x str>,
+    ) -> trc::Result<Option<AiTenantEndpoint>> {
+        let mut result: Option<AiTenantEndpoint> = None;
+        let mut seen = Vec::new();
+
+        for domain in domains {
+            let tenant_id = self.ai_tenant_for_domain(domain).await?;
+            if seen.contains(&tenant_id) {
+                continue;
+            }
+            seen.push(tenant_id);
+            let Some(endpoint) = self.ai_endpoint_for_tenant(default, tenant_id).await? else {
+                return Ok(None);
+            };
+
+            if let Some(result) = &mut result {
+                if result.endpoint.id() != endpoint.endpoint.id() {
+                    trc::event!(
+                        Ai(trc::AiEvent::ApiError),
+                        Details = "Recipients belong to tenants with different AI policies",
+                        Domain = domain.to_string(),
+                    );
+                    return Ok(None);
+                }
+                // Usage is accounted globally when the tenants differ
+                result.tenant_id = None;
+            } else {
+                result = Some(endpoint);
+            }
+        }
+
+        Ok(result)
+    }
+
+    // 按租户解析端点，租户禁用 AI 时返回 None
+    pub async fn ai_endpoint_for_tenant(
+        &self,
+        default: &AiEndpoint,
+        tenant_id: Option<u32>,
+    ) -> trc::Result<Option<AiTenantEndpoint>> {
+        match self.ai_tenant_policy(tenant_id).await? {
+            Some(AiTenantPolicy::Disable) => Ok(None),
+            Some(AiTenantPolicy::Override(endpoint)) => Ok(Some(AiTenantEndpoint {
+                endpoint: endpoint.clone(),
+                tenant_id,
+            })),
+            None => Ok(Some(AiTenantEndpoint {
+                endpoint: default.clone(),
+                tenant_id,
+            })),
+        }
+    }
+
+    // 返回租户的 AI 策略
+    pub async fn ai_tenant_policy(
+        &self,
+        tenant_id: Option<u32>,
+    ) -> trc::Result<Option<&AiTenantPolicy>> {
+        let Some(tenant_id) = tenant_id.filter(|_| self.has_ai_tenant_policies()) else {
+            return Ok(None);
+        };
+
+        Ok(self
+            .store()
+            .query(QueryBy::Id(tenant_id), false)
+            .await
+            .caused_by(trc::location!())?
+            .and_then(|tenant| {
+                self.core
+                    .enterprise
+                    .as_ref()
+                    .and_then(|e| e.ai_tenants.get(tenant.name()))
+            }))
+    }
+
+    // 每个请求都解析租户用于用量统计，无论是否配置了租户策略
+    async fn ai_tenant_for_domain(&self, domain: &str) -> trc::Result<Option<u32>> {
+        Ok(self
+            .store()
+            .query(QueryBy::Name(&domain.to_lowercase()), false)
+            .await
+            .caused_by(trc::location!())?
+            .filter(|p| p.typ() == Type::Domain)
+            .and_then(|p| p.get_int(PrincipalField::Tenant))
+            .map(|tenant_id| tenant_id as u32))
+    }
+
+    fn has_ai_tenant_policies(&self) -> bool {
+        self.core
+            .enterprise
+            .as_ref()
+            .is_some_and(|e| !e.ai_tenants.is_empty())
+    }
+}
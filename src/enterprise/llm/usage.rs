@@ -0,0 +1,211 @@
+use mail_parser::DateTime;
+use store::{
+    write::{key::KeySerializer, now, BatchBuilder, InMemoryClass, ValueClass},
+    Store, ValueKey, U32_LEN, U64_LEN,
+};
+use trc::AddContext;
+use utils::config::Config;
+
+const USAGE_PREFIX: &[u8] = b"ai-usage.";
+
+// 单个请求消耗的令牌数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AiUsage {
+    pub input_tokens: u64,
+    pub output_tokens: u64,
+}
+
+// 端点在周期内的令牌上限
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AiBudget {
+    pub daily: Option<u64>,
+    pub monthly: Option<u64>,
+    pub tenant_daily: Option<u64>,
+    pub tenant_monthly: Option<u64>,
+}
+
+// 按端点和租户统计令牌用量，计数器保存在数据存储中，集群内共享
+#[derive(Debug, Clone, Default)]
+pub struct AiUsageTracker {
+    pub id: String,
+    pub budget: AiBudget,
+    pub store: Option<Store>,
+}
+
+// 当天和当月消耗的令牌数
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AiUsagePeriod {
+    pub daily_tokens: u64,
+    pub monthly_tokens: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum UsagePeriod {
+    Day(u64),
+    Month(u64),
+}
+
+impl AiUsage {
+    pub fn total(&self) -> u64 {
+        self.input_tokens + self.output_tokens
+    }
+}
+
+impl AiUsageTracker {
+    pub async fn record(&self, tenant_id: Option<u32>, usage: Option<AiUsage>) {
+        let Some(usage) = usage.filter(|usage| usage.total() > 0) else {
+            return;
+        };
+
+        // 按端点和租户标记的用量指标
+        trc::event!(
+            Ai(trc::AiEvent::InputTokens),
+            Id = self.id.clone(),
+            Value = tenant_id,
+            Total = usage.input_tokens,
+        );
+        trc::event!(
+            Ai(trc::AiEvent::OutputTokens),
+            Id = self.id.clone(),
+            Value = tenant_id,
+            Total = usage.output_tokens,
+        );
+
+        if let Some(store) = &self.store {
+            let (day, month) = current_period();
+            let mut batch = BatchBuilder::new();
+            for tenant_id in [Some(None), tenant_id.map(Some)].into_iter().flatten() {
+                for period in [UsagePeriod::Day(day), UsagePeriod::Month(month)] {
+                    batch.add(
+                        self.counter_key(tenant_id, period).class,
+                        usage.total() as i64,
+                    );
+                }
+            }
+
+            if let Err(err) = store.write(batch.build()).await {
+                trc::error!(err
+                    .id(self.id.clone())
+                    .details("Failed to record AI token usage")
+                    .caused_by(trc::location!()));
+            }
+        }
+    }
+
+    // 端点或租户是否已用完当天或当月的令牌预算
+    pub async fn is_over_budget(&self, tenant_id: Option<u32>) -> trc::Result<bool> {
+        let budget = &self.budget;
+        if budget.daily.is_some() || budget.monthly.is_some() {
+            let usage = self.usage(None).await?;
+            if usage.exceeds(budget.daily, budget.monthly) {
+                return Ok(true);
+            }
+        }
+
+        if tenant_id.is_some() && (budget.tenant_daily.is_some() || budget.tenant_monthly.is_some())
+        {
+            let usage = self.usage(tenant_id).await?;
+            if usage.exceeds(budget.tenant_daily, budget.tenant_monthly) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    // 返回当天和当月的令牌用量，总计或单个租户
+    pub async fn usage(&self, tenant_id: Option<u32>) -> trc::Result<AiUsagePeriod> {
+        let Some(store) = &self.store else {
+            return Ok(AiUsagePeriod::default());
+        };
+
+        let (day, month) = current_period();
+        Ok(AiUsagePeriod {
+            daily_tokens: store
+                .get_counter(self.counter_key(tenant_id, UsagePeriod::Day(day)))
+                .await
+                .caused_by(trc::location!())?
+                .max(0) as u64,
+            monthly_tokens: store
+                .get_counter(self.counter_key(tenant_id, UsagePeriod::Month(month)))
+                .await
+                .caused_by(trc::location!())?
+                .max(0) as u64,
+        })
+    }
+
+    // 计数器键以周期开头，便于按范围删除过去周期的计数器
+    fn counter_key(&self, tenant_id: Option<u32>, period: UsagePeriod) -> ValueKey<ValueClass> {
+        let key = period_key(period).write(self.id.as_bytes()).write(0u8);
+        counter_key(match tenant_id {
+            Some(tenant_id) => key.write(1u8).write(tenant_id),
+            None => key.write(0u8),
+        })
+    }
+
+    pub fn parse(config: &mut Config, id: &str) -> Self {
+        AiUsageTracker {
+            id: id.to_string(),
+            budget: AiBudget {
+                daily: config.property(("enterprise.ai", id, "budget.daily")),
+                monthly: config.property(("enterprise.ai", id, "budget.monthly")),
+                tenant_daily: config.property(("enterprise.ai", id, "budget.tenant-daily")),
+                tenant_monthly: config.property(("enterprise.ai", id, "budget.tenant-monthly")),
+            },
+            store: None,
+        }
+    }
+
+    pub fn with_store(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
+}
+
+impl AiUsagePeriod {
+    fn exceeds(&self, daily: Option<u64>, monthly: Option<u64>) -> bool {
+        daily.is_some_and(|limit| self.daily_tokens >= limit)
+            || monthly.is_some_and(|limit| self.monthly_tokens >= limit)
+    }
+}
+
+// 删除所有端点在当前日和当前月之前的用量计数器
+pub async fn purge_usage(store: &Store) -> trc::Result<()> {
+    let (day, month) = current_period();
+    for (from, to) in [
+        (UsagePeriod::Day(0), UsagePeriod::Day(day)),
+        (UsagePeriod::Month(0), UsagePeriod::Month(month)),
+    ] {
+        store
+            .delete_range(counter_key(period_key(from)), counter_key(period_key(to)))
+            .await
+            .caused_by(trc::location!())?;
+    }
+    Ok(())
+}
+
+fn period_key(period: UsagePeriod) -> KeySerializer {
+    let (kind, value) = match period {
+        UsagePeriod::Day(day) => (b'd', day),
+        UsagePeriod::Month(month) => (b'm', month),
+    };
+    KeySerializer::new(USAGE_PREFIX.len() + U64_LEN + U32_LEN + 32)
+        .write(USAGE_PREFIX)
+        .write(kind)
+        .write(value)
+}
+
+fn counter_key(key: KeySerializer) -> ValueKey<ValueClass> {
+    ValueKey {
+        account_id: 0,
+        collection: 0,
+        document_id: 0,
+        class: ValueClass::InMemory(InMemoryClass::Counter(key.finalize())),
+    }
+}
+
+fn current_period() -> (u64, u64) {
+    let now = now();
+    let date = DateTime::from_timestamp(now as i64);
+    (now / 86400, date.year as u64 * 12 + date.month as u64)
+}
@@ -12,7 +12,12 @@ use directory::{
     QueryBy, Type,
 };
 use license::LicenseKey;
-use llm::AiApiConfig;
+use llm::{
+    audit::AiAuditLog,
+    pool::{AiApiPool, AiEndpoint},
+    tenant::AiTenantPolicy,
+    AiApiConfig,
+};
 use mail_parser::DateTime;
 use store::Store;
 use trc::{AddContext, EventType, MetricType};
@@ -29,20 +34,117 @@ pub struct Enterprise {
     pub metrics_store: Option<MetricStore>,
     pub metrics_alerts: Vec<MetricAlert>,
     pub ai_apis: AHashMap<String, Arc<AiApiConfig>>,
+    pub ai_pools: AHashMap<String, Arc<AiApiPool>>,
+    pub ai_tenants: AHashMap<String, AiTenantPolicy>,
     pub spam_filter_llm: Option<SpamFilterLlmConfig>,
+    pub ai_assistant: Option<AiAssistantConfig>,
+    pub ai_sieve: Option<AiSieveConfig>,
+    pub phishing_llm: Option<PhishingLlmConfig>,
+    pub ai_embedding: Option<AiEmbeddingConfig>,
+    pub ai_audit: Option<Arc<AiAuditLog>>,
 }
 
 #[derive(Debug, Clone)]
 pub struct SpamFilterLlmConfig {
-    pub model: Arc<AiApiConfig>,
+    pub model: AiEndpoint,
     pub temperature: f64,
-    pub prompt: String,
+    pub prompt: LlmPromptTemplate,
+    pub message: LlmPromptTemplate,
     pub separator: char,
     pub index_category: usize,
     pub index_confidence: Option<usize>,
     pub index_explanation: Option<usize>,
     pub categories: AHashSet<String>,
     pub confidence: AHashSet<String>,
+    pub examples: Vec<(String, String)>,
+    pub response_format: LlmResponseFormat,
+    pub scores: Vec<LlmScoreRule>,
+    pub condition: Option<Expression>,
+    pub sample_rate: f64,
+    pub exclude_domains: AHashSet<String>,
+    pub mode: LlmClassificationMode,
+    pub deferred_junk_score: f64,
+    pub deferred_queue_size: u64,
+    pub deferred_concurrency: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct PhishingLlmConfig {
+    pub model: AiEndpoint,
+    pub temperature: f64,
+    pub body_limit: usize,
+    pub score: f64,
+    pub min_confidence: f64,
+    pub header: String,
+}
+
+#[derive(Debug, Clone)]
+pub struct AiAssistantConfig {
+    pub model: AiEndpoint,
+    pub temperature: f64,
+    pub message: LlmPromptTemplate,
+    pub categories: Vec<String>,
+    pub summary_length: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct AiEmbeddingConfig {
+    pub model: Arc<AiApiConfig>,
+    pub max_input: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct AiSieveConfig {
+    pub model: AiEndpoint,
+    pub temperature: f64,
+    pub max_attempts: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct LlmScoreRule {
+    pub category: String,
+    pub confidence: Option<String>,
+    pub score: f64,
+    pub tags: Vec<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmClassificationMode {
+    Inline,
+    Deferred,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LlmResponseFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct LlmPromptTemplate(pub Vec<LlmPromptToken>);
+
+#[derive(Debug, Clone)]
+pub enum LlmPromptToken {
+    Text(String),
+    Variable {
+        variable: LlmPromptVariable,
+        limit: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LlmPromptVariable {
+    Subject,
+    From,
+    Sender,
+    To,
+    Headers,
+    Body,
+    Spf,
+    Dkim,
+    Dmarc,
+    Tags,
+    Score,
 }
 
 #[derive(Clone)]
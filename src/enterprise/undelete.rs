@@ -1,4 +1,6 @@
+use jmap_proto::types::collection::Collection; // 引入集合类型
 use serde::{Deserialize, Serialize}; // 引入serde库用于序列化和反序列化
+use std::io::{Seek, Write}; // 引入用于流式写入归档的模块
 use store::{
     write::{
         key::{DeserializeBigEndian, KeySerializer}, // 引入用于序列化键的模块
@@ -23,6 +25,80 @@ pub struct DeletedBlob<H, T, C> {
     pub collection: C, // 集合
 }
 
+// 定义DeletedObject结构体，用于表示已删除的Blob及其对象元数据
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeletedObject<H, T> {
+    #[serde(flatten)]
+    pub blob: DeletedBlob<H, T, DeletedCollection>, // 已删除的Blob
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub metadata: Option<DeletedMetadata>, // 非邮件集合的对象元数据
+}
+
+// 定义DeletedCollection枚举，用于以稳定名称表示已删除Blob所属的集合
+// 未知集合以其原始ID序列化，避免丢失信息
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum DeletedCollection {
+    Email, // 邮件
+    Sieve, // Sieve脚本
+    Calendar, // 日历事件
+    Contact, // 联系人
+    File, // 文件
+    Unknown(u8), // 未知集合及其原始ID
+}
+
+// 定义DeletedCollectionSummary结构体，用于按集合分组已删除的Blob
+#[derive(Debug, Serialize, Deserialize)]
+pub struct DeletedCollectionSummary<H, T> {
+    pub collection: DeletedCollection, // 集合
+    pub count: usize, // 数量
+    pub size: usize, // 总大小
+    pub items: Vec<DeletedObject<H, T>>, // 已删除的对象
+}
+
+// 定义DeletedMetadata枚举，用于保存Blob中不包含的对象元数据，以便完整恢复
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "camelCase")]
+pub enum DeletedMetadata {
+    // 日历事件
+    Calendar {
+        uid: String, // 事件UID
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        name: Option<String>, // 资源名称
+        #[serde(rename = "parentIds", default)]
+        parent_ids: Vec<u32>, // 所属日历ID
+    },
+    // 联系人
+    Contact {
+        uid: String, // vCard UID
+        #[serde(skip_serializing_if = "Option::is_none", default)]
+        name: Option<String>, // 资源名称
+        #[serde(rename = "parentIds", default)]
+        parent_ids: Vec<u32>, // 所属通讯录ID
+    },
+    // 文件
+    File {
+        path: String, // 文件路径
+        #[serde(rename = "mediaType", skip_serializing_if = "Option::is_none", default)]
+        media_type: Option<String>, // 媒体类型
+        #[serde(rename = "parentId", skip_serializing_if = "Option::is_none", default)]
+        parent_id: Option<u32>, // 父目录ID
+    },
+}
+
+// 定义DeletedExport结构体，用于表示导出结果，Blob缺失的邮件计入skipped
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DeletedExport {
+    pub exported: usize, // 已导出的邮件数量
+    pub skipped: usize, // 因Blob缺失而跳过的邮件数量
+}
+
+// 定义DeletedExportFormat枚举，用于表示已删除邮件的导出格式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeletedExportFormat {
+    Mbox, // mbox格式
+    Eml, // 包含.eml文件的zip压缩包
+}
+
 // 为Core结构体实现方法
 impl Core {
     // 定义hold_undelete方法，用于保留删除操作
@@ -32,6 +108,38 @@ impl Core {
         collection: u8, // 集合ID
         blob_hash: &BlobHash, // Blob哈希值
         blob_size: usize, // Blob大小
+    ) {
+        self.reserve_undelete(batch, collection, blob_hash, blob_size, &[]);
+    }
+
+    // 定义hold_undelete_with_metadata方法，用于保留删除操作及对象元数据，元数据无法序列化时返回错误
+    pub fn hold_undelete_with_metadata(
+        &self,
+        batch: &mut BatchBuilder, // 批量构建器
+        collection: u8, // 集合ID
+        blob_hash: &BlobHash, // Blob哈希值
+        blob_size: usize, // Blob大小
+        metadata: &DeletedMetadata, // 对象元数据
+    ) -> trc::Result<()> {
+        let metadata = serde_json::to_vec(metadata).map_err(|err| {
+            trc::StoreEvent::UnexpectedError
+                .into_err()
+                .details("Failed to serialize undelete metadata")
+                .reason(err)
+                .caused_by(trc::location!())
+        })?;
+        self.reserve_undelete(batch, collection, blob_hash, blob_size, &metadata);
+        Ok(())
+    }
+
+    // 写入保留操作
+    fn reserve_undelete(
+        &self,
+        batch: &mut BatchBuilder, // 批量构建器
+        collection: u8, // 集合ID
+        blob_hash: &BlobHash, // Blob哈希值
+        blob_size: usize, // Blob大小
+        metadata: &[u8], // 序列化后的对象元数据
     ) {
         // 检查是否存在undelete配置
         if let Some(undelete) = self.enterprise.as_ref().and_then(|e| e.undelete.as_ref()) {
@@ -43,11 +151,7 @@ impl Core {
                     hash: blob_hash.clone(), // 克隆Blob哈希值
                     until: now + undelete.retention.as_secs(), // 设置保留时间
                 },
-                KeySerializer::new(U64_LEN + U64_LEN)
-                    .write(blob_size as u32) // 写入Blob大小
-                    .write(now) // 写入当前时间
-                    .write(collection) // 写入集合ID
-                    .finalize(), // 完成序列化
+                serialize_hold(blob_size, now, collection, metadata),
             );
         }
     }
@@ -57,6 +161,63 @@ impl Core {
         &self,
         account_id: u32, // 账户ID
     ) -> trc::Result<Vec<DeletedBlob<BlobHash, u64, u8>>> {
+        let mut results = Vec::new(); // 初始化结果向量
+
+        self.iterate_deleted(account_id, |blob, _| {
+            results.push(blob);
+            Ok(())
+        })
+        .await
+        .caused_by(trc::location!())?;
+
+        Ok(results) // 返回结果
+    }
+
+    // 定义list_deleted_objects方法，用于列出已删除的Blob及其对象元数据
+    pub async fn list_deleted_objects(
+        &self,
+        account_id: u32, // 账户ID
+    ) -> trc::Result<Vec<DeletedObject<BlobHash, u64>>> {
+        let mut results = Vec::new(); // 初始化结果向量
+
+        self.iterate_deleted(account_id, |blob, metadata| {
+            // 元数据损坏时记录错误并保留该条目，Blob本身仍可恢复
+            let metadata = match metadata
+                .filter(|metadata| !metadata.is_empty())
+                .map(serde_json::from_slice::<DeletedMetadata>)
+                .transpose()
+            {
+                Ok(metadata) => metadata,
+                Err(err) => {
+                    trc::error!(trc::StoreEvent::DataCorruption
+                        .into_err()
+                        .details("Failed to deserialize undelete metadata")
+                        .account_id(account_id)
+                        .reason(err)
+                        .caused_by(trc::location!()));
+                    None
+                }
+            };
+            results.push(DeletedObject {
+                blob: blob.with_collection_name(),
+                metadata,
+            });
+            Ok(())
+        })
+        .await
+        .caused_by(trc::location!())?;
+
+        Ok(results) // 返回结果
+    }
+
+    // 迭代账户中所有未过期的保留操作，并传入对象元数据的原始字节
+    async fn iterate_deleted(
+        &self,
+        account_id: u32, // 账户ID
+        mut cb: impl FnMut(DeletedBlob<BlobHash, u64, u8>, Option<&[u8]>) -> trc::Result<()>
+        + Send
+        + Sync,
+    ) -> trc::Result<()> {
         let from_key = ValueKey {
             account_id, // 设置起始键的账户ID
             collection: 0,
@@ -77,7 +238,6 @@ impl Core {
         };
 
         let now = now(); // 获取当前时间
-        let mut results = Vec::new(); // 初始化结果向量
 
         // 迭代存储数据
         self.storage
@@ -85,27 +245,417 @@ impl Core {
             .iterate(
                 IterateParams::new(from_key, to_key).ascending(), // 设置迭代参数
                 |key, value| {
-                    let expires_at = key.deserialize_be_u64(key.len() - U64_LEN)?; // 反序列化过期时间
-                    if value.len() == U32_LEN + U64_LEN + 1 && expires_at > now {
-                        results.push(DeletedBlob {
-                            hash: BlobHash::try_from_hash_slice(
-                                key.get(U32_LEN..U32_LEN + BLOB_HASH_LEN).ok_or_else(|| {
-                                    trc::Error::corrupted_key(key, value.into(), trc::location!())
-                                })?,
-                            )
-                            .unwrap(), // 获取Blob哈希值
-                            size: value.deserialize_be_u32(0)? as usize, // 获取Blob大小
-                            deleted_at: value.deserialize_be_u64(U32_LEN)?, // 获取删除时间
-                            expires_at, // 获取过期时间
-                            collection: *value.last().unwrap(), // 获取集合ID
-                        });
+                    if let Some((blob, metadata)) = deserialize_hold(key, value, now)? {
+                        cb(blob, metadata)?; // 传入对象元数据
                     }
                     Ok(true)
                 },
             )
             .await
+            .caused_by(trc::location!())
+    }
+
+    // 定义list_deleted_by_collection方法，用于按集合分组列出已删除的Blob
+    pub async fn list_deleted_by_collection(
+        &self,
+        account_id: u32, // 账户ID
+    ) -> trc::Result<Vec<DeletedCollectionSummary<BlobHash, u64>>> {
+        self.list_deleted_objects(account_id)
+            .await
+            .map(DeletedCollectionSummary::group)
+    }
+
+    // 定义export_deleted方法，用于将可恢复的邮件以流的方式写入mbox或.eml压缩包
+    pub async fn export_deleted<W: Write + Seek>(
+        &self,
+        account_id: u32, // 账户ID
+        from: Option<u64>, // 删除时间下限（包含）
+        to: Option<u64>, // 删除时间上限（不包含）
+        format: DeletedExportFormat, // 导出格式
+        writer: &mut W, // 输出目标（文件或其他可定位的写入器）
+    ) -> trc::Result<DeletedExport> {
+        match format {
+            DeletedExportFormat::Mbox => {
+                self.export_deleted_mbox(account_id, from, to, writer).await
+            }
+            DeletedExportFormat::Eml => self.export_deleted_eml(account_id, from, to, writer).await,
+        }
+    }
+
+    // 以mbox格式导出，只需要顺序写入
+    pub async fn export_deleted_mbox<W: Write>(
+        &self,
+        account_id: u32, // 账户ID
+        from: Option<u64>, // 删除时间下限（包含）
+        to: Option<u64>, // 删除时间上限（不包含）
+        writer: &mut W, // 输出目标
+    ) -> trc::Result<DeletedExport> {
+        self.export_deleted_with(account_id, from, to, |_, blob, raw_message| {
+            write_mbox_message(writer, raw_message, blob.deleted_at).map_err(|err| {
+                trc::StoreEvent::UnexpectedError
+                    .into_err()
+                    .details("Failed to write mbox archive")
+                    .reason(err)
+            })
+        })
+        .await
+    }
+
+    // 以包含.eml文件的zip压缩包导出，zip格式需要可定位的写入器
+    pub async fn export_deleted_eml<W: Write + Seek>(
+        &self,
+        account_id: u32, // 账户ID
+        from: Option<u64>, // 删除时间下限（包含）
+        to: Option<u64>, // 删除时间上限（不包含）
+        writer: &mut W, // 输出目标
+    ) -> trc::Result<DeletedExport> {
+        let mut zip = zip::ZipWriter::new(writer);
+        let result = self
+            .export_deleted_with(account_id, from, to, |exported, blob, raw_message| {
+                zip.start_file(
+                    format!("{:05}-{}.eml", exported, blob.deleted_at),
+                    zip::write::SimpleFileOptions::default(),
+                )
+                .and_then(|_| zip.write_all(raw_message).map_err(Into::into))
+                .map_err(|err| {
+                    trc::StoreEvent::UnexpectedError
+                        .into_err()
+                        .details("Failed to write zip archive")
+                        .reason(err)
+                })
+            })
+            .await?;
+
+        zip.finish().map_err(|err| {
+            trc::StoreEvent::UnexpectedError
+                .into_err()
+                .details("Failed to write zip archive")
+                .reason(err)
+        })?;
+
+        Ok(result)
+    }
+
+    // 按删除时间顺序逐封获取可恢复的邮件并交给写入回调，Blob缺失的邮件记录警告后跳过
+    async fn export_deleted_with(
+        &self,
+        account_id: u32, // 账户ID
+        from: Option<u64>, // 删除时间下限（包含）
+        to: Option<u64>, // 删除时间上限（不包含）
+        mut write: impl FnMut(usize, &DeletedBlob<BlobHash, u64, u8>, &[u8]) -> trc::Result<()>,
+    ) -> trc::Result<DeletedExport> {
+        let mut deleted = self
+            .list_deleted(account_id)
+            .await
             .caused_by(trc::location!())?;
+        deleted.retain(|blob| {
+            DeletedCollection::from(blob.collection) == DeletedCollection::Email
+                && from.is_none_or(|from| blob.deleted_at >= from)
+                && to.is_none_or(|to| blob.deleted_at < to)
+        });
+        deleted.sort_unstable_by_key(|blob| blob.deleted_at); // 按删除时间排序
 
-        Ok(results) // 返回结果
+        let mut result = DeletedExport::default();
+
+        for blob in deleted {
+            // 从Blob存储中逐封获取原始邮件，避免在内存中构建整个归档
+            let Some(raw_message) = self
+                .storage
+                .blob
+                .get_blob(blob.hash.as_slice(), 0..usize::MAX)
+                .await
+                .caused_by(trc::location!())?
+            else {
+                trc::event!(
+                    Store(trc::StoreEvent::NotFound),
+                    AccountId = account_id,
+                    Details = "Blob of deleted message not found, skipping export",
+                    Size = blob.size,
+                );
+                result.skipped += 1;
+                continue;
+            };
+            result.exported += 1;
+
+            write(result.exported, &blob, &raw_message)?;
+        }
+
+        Ok(result)
+    }
+}
+
+// 为DeletedCollection枚举实现方法
+impl DeletedCollection {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            DeletedCollection::Email => "email",
+            DeletedCollection::Sieve => "sieve",
+            DeletedCollection::Calendar => "calendar",
+            DeletedCollection::Contact => "contact",
+            DeletedCollection::File => "file",
+            DeletedCollection::Unknown(_) => "unknown",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "email" => Some(DeletedCollection::Email),
+            "sieve" => Some(DeletedCollection::Sieve),
+            "calendar" => Some(DeletedCollection::Calendar),
+            "contact" => Some(DeletedCollection::Contact),
+            "file" => Some(DeletedCollection::File),
+            _ => value.parse().ok().map(DeletedCollection::Unknown),
+        }
+    }
+}
+
+// 已知集合序列化为稳定名称，未知集合序列化为原始ID
+impl std::fmt::Display for DeletedCollection {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DeletedCollection::Unknown(id) => write!(f, "{id}"),
+            collection => f.write_str(collection.as_str()),
+        }
+    }
+}
+
+impl Serialize for DeletedCollection {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+impl<'de> Deserialize<'de> for DeletedCollection {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        DeletedCollection::parse(&value)
+            .ok_or_else(|| serde::de::Error::custom(format!("Unknown collection {value:?}")))
+    }
+}
+
+// 为使用原始集合ID的DeletedBlob提供到稳定集合名称的转换
+impl<H, T> DeletedBlob<H, T, u8> {
+    pub fn with_collection_name(self) -> DeletedBlob<H, T, DeletedCollection> {
+        DeletedBlob {
+            hash: self.hash,
+            size: self.size,
+            deleted_at: self.deleted_at,
+            expires_at: self.expires_at,
+            collection: DeletedCollection::from(self.collection),
+        }
+    }
+}
+
+// 将集合ID转换为DeletedCollection
+impl From<u8> for DeletedCollection {
+    fn from(collection: u8) -> Self {
+        match Collection::from(collection) {
+            Collection::Email => DeletedCollection::Email,
+            Collection::SieveScript => DeletedCollection::Sieve,
+            Collection::CalendarEvent => DeletedCollection::Calendar,
+            Collection::ContactCard => DeletedCollection::Contact,
+            Collection::FileNode => DeletedCollection::File,
+            _ => DeletedCollection::Unknown(collection),
+        }
+    }
+}
+
+// 为DeletedCollectionSummary结构体实现方法
+impl<H, T> DeletedCollectionSummary<H, T> {
+    // 按集合对已删除的Blob进行分组，并统计数量和总大小
+    pub fn group(items: Vec<DeletedObject<H, T>>) -> Vec<Self> {
+        let mut groups: Vec<Self> = Vec::new();
+
+        for item in items {
+            let group = if let Some(pos) = groups
+                .iter()
+                .position(|group| group.collection == item.blob.collection)
+            {
+                &mut groups[pos]
+            } else {
+                groups.push(DeletedCollectionSummary {
+                    collection: item.blob.collection,
+                    count: 0,
+                    size: 0,
+                    items: Vec::new(),
+                });
+                groups.last_mut().unwrap()
+            };
+            group.count += 1;
+            group.size += item.blob.size;
+            group.items.push(item);
+        }
+
+        groups.sort_unstable_by_key(|group| group.collection);
+        groups
+    }
+}
+
+// 为DeletedExportFormat枚举实现方法
+impl DeletedExportFormat {
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "mbox" => Some(DeletedExportFormat::Mbox),
+            "eml" | "zip" => Some(DeletedExportFormat::Eml),
+            _ => None,
+        }
+    }
+
+    pub fn content_type(&self) -> &'static str {
+        match self {
+            DeletedExportFormat::Mbox => "application/mbox",
+            DeletedExportFormat::Eml => "application/zip",
+        }
+    }
+
+    pub fn extension(&self) -> &'static str {
+        match self {
+            DeletedExportFormat::Mbox => "mbox",
+            DeletedExportFormat::Eml => "zip",
+        }
+    }
+}
+
+// 序列化保留操作的值：Blob大小、删除时间、集合ID和对象元数据
+fn serialize_hold(blob_size: usize, deleted_at: u64, collection: u8, metadata: &[u8]) -> Vec<u8> {
+    KeySerializer::new(U32_LEN + U64_LEN + 1 + metadata.len())
+        .write(blob_size as u32) // 写入Blob大小
+        .write(deleted_at) // 写入删除时间
+        .write(collection) // 写入集合ID
+        .write(metadata) // 写入对象元数据
+        .finalize()
+}
+
+// 解析保留操作的键和值，已过期或格式不完整的条目返回None
+fn deserialize_hold<'x>(
+    key: &[u8],
+    value: &'x [u8],
+    now: u64,
+) -> trc::Result<Option<(DeletedBlob<BlobHash, u64, u8>, Option<&'x [u8]>)>> {
+    let expires_at = key.deserialize_be_u64(key.len() - U64_LEN)?; // 反序列化过期时间
+    if value.len() < U32_LEN + U64_LEN + 1 || expires_at <= now {
+        return Ok(None);
+    }
+
+    let blob = DeletedBlob {
+        hash: BlobHash::try_from_hash_slice(
+            key.get(U32_LEN..U32_LEN + BLOB_HASH_LEN)
+                .ok_or_else(|| trc::Error::corrupted_key(key, value.into(), trc::location!()))?,
+        )
+        .unwrap(), // 获取Blob哈希值
+        size: value.deserialize_be_u32(0)? as usize, // 获取Blob大小
+        deleted_at: value.deserialize_be_u64(U32_LEN)?, // 获取删除时间
+        expires_at, // 获取过期时间
+        collection: value[U32_LEN + U64_LEN], // 获取集合ID
+    };
+    Ok(Some((blob, value.get(U32_LEN + U64_LEN + 1..))))
+}
+
+// 以mboxrd格式写入单封邮件，统一使用LF换行，与"From "分隔行保持一致
+fn write_mbox_message(
+    output: &mut impl Write,
+    raw_message: &[u8],
+    deleted_at: u64,
+) -> std::io::Result<()> {
+    let date = chrono::DateTime::from_timestamp(deleted_at as i64, 0).unwrap_or_default();
+    writeln!(output, "From MAILER-DAEMON {}", date.format("%a %b %e %H:%M:%S %Y"))?;
+
+    for line in raw_message.split_inclusive(|&ch| ch == b'\n') {
+        let line = line
+            .strip_suffix(b"\r\n")
+            .or_else(|| line.strip_suffix(b"\n"))
+            .unwrap_or(line);
+
+        // 对以">*From "开头的行进行转义
+        if line
+            .iter()
+            .skip_while(|&&ch| ch == b'>')
+            .take(5)
+            .eq(b"From ".iter())
+        {
+            output.write_all(b">")?;
+        }
+        output.write_all(line)?;
+        output.write_all(b"\n")?;
+    }
+
+    output.write_all(b"\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use utils::BLOB_HASH_LEN;
+
+    use super::{
+        deserialize_hold, serialize_hold, write_mbox_message, DeletedCollection, DeletedMetadata,
+    };
+
+    #[test]
+    fn collection_names() {
+        for (collection, name) in [
+            (DeletedCollection::Email, "\"email\""),
+            (DeletedCollection::File, "\"file\""),
+            (DeletedCollection::Unknown(42), "\"42\""),
+        ] {
+            assert_eq!(serde_json::to_string(&collection).unwrap(), name);
+            assert_eq!(
+                serde_json::from_str::<DeletedCollection>(name).unwrap(),
+                collection
+            );
+        }
+    }
+
+    #[test]
+    fn mbox_message_line_endings() {
+        let mut output = Vec::new();
+        write_mbox_message(
+            &mut output,
+            b"Subject: test\r\n\r\nFrom here\r\n>From there\r\nlast line",
+            0,
+        )
+        .unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            concat!(
+                "From MAILER-DAEMON Thu Jan  1 00:00:00 1970\n",
+                "Subject: test\n",
+                "\n",
+                ">From here\n",
+                ">>From there\n",
+                "last line\n",
+                "\n"
+            )
+        );
+    }
+
+    #[test]
+    fn hold_metadata_roundtrip() {
+        let metadata = DeletedMetadata::File {
+            path: "/docs/report.pdf".to_string(),
+            media_type: Some("application/pdf".to_string()),
+            parent_id: Some(3),
+        };
+        let value = serialize_hold(1024, 1000, 6, &serde_json::to_vec(&metadata).unwrap());
+        let mut key = 7u32.to_be_bytes().to_vec();
+        key.extend_from_slice(&[0xAB; BLOB_HASH_LEN]);
+        key.extend_from_slice(&2000u64.to_be_bytes());
+
+        let (blob, raw_metadata) = deserialize_hold(&key, &value, 1500).unwrap().unwrap();
+        assert_eq!(blob.hash.as_slice(), &[0xAB; BLOB_HASH_LEN]);
+        assert_eq!(blob.size, 1024);
+        assert_eq!(blob.deleted_at, 1000);
+        assert_eq!(blob.expires_at, 2000);
+        assert_eq!(blob.collection, 6);
+        assert_eq!(
+            serde_json::from_slice::<DeletedMetadata>(raw_metadata.unwrap()).unwrap(),
+            metadata
+        );
+
+        // 无元数据的保留操作和已过期的保留操作
+        let value = serialize_hold(1024, 1000, 1, &[]);
+        let (_, raw_metadata) = deserialize_hold(&key, &value, 1500).unwrap().unwrap();
+        assert_eq!(raw_metadata, Some(&[][..]));
+        assert!(deserialize_hold(&key, &value, 2000).unwrap().is_none());
     }
 }